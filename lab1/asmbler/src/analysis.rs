@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use isa::{Access, Flow, MEMORY_SIZE};
+
+use crate::disasm::instruction_text;
+use crate::encoder::{decode, format_word};
+
+// --analyze, a control flow graph of the program from 0 and what can go wrong along it
+//
+// an .asml input is assembled first, so every word is known to be code or data, an .sml input
+// is only words, one is code when it is reached and decodes to an instruction
+//
+// where jmp idx and jmp [lit] go is not in the word, once the program has one every labelled
+// address loaded with mov acc lit or kept in a data word is taken to be reachable from it,
+// that is how call and ret come back, in an .sml program any such address counts
+//
+// reads of memory nothing wrote and division by a 0 that nothing overwrites are only reported
+// when the program stores nothing through idx or a pointer, which could write any cell
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Code,
+    Data,
+    // a word from an .sml file
+    Unknown,
+}
+
+pub struct Cell {
+    pub word: i32,
+    pub kind: Kind,
+    // the line it came from, None for words the tools put in, the jmp to the entry or the final hlt
+    pub line_num: Option<usize>,
+    // something refers to the address by name, only asml knows
+    pub labelled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    // code nothing reaches, reported on the first word of each run of it
+    UnreachableCode,
+    // the next word is not part of the program
+    FallsOffEnd,
+    // goes to a word that is data or is not an instruction
+    ExecutesData(u32),
+    // goes to an address outside the program
+    JumpsOutOfProgram(i32),
+    ReadsUninitialized(u32),
+    StoreIntoCode(u32),
+    DivisionByZero(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Next,
+    // a jump, or a branch when its condition holds
+    Taken,
+    // from jmp idx or jmp [lit] to somewhere it may go
+    Indirect,
+}
+
+pub struct Analysis {
+    // addresses run as instructions
+    pub reached: BTreeSet<u32>,
+    pub edges: Vec<(u32, u32, Edge)>,
+    // the address of the word each warning is about
+    pub warnings: Vec<(Warning, u32)>,
+}
+
+fn executable(cell: &Cell) -> bool {
+    match cell.kind {
+        Kind::Code => true,
+        Kind::Data => false,
+        Kind::Unknown => {
+            let (opcode, operand) = decode(cell.word);
+            isa::by_opcode(opcode).is_some_and(|instr| instr.form_for(operand).is_some())
+        }
+    }
+}
+
+pub fn analyze(cells: &BTreeMap<u32, Cell>) -> Analysis {
+    let mut analysis = Analysis { reached: BTreeSet::new(), edges: Vec::new(), warnings: Vec::new() };
+    let mut indirect_jumps = BTreeSet::new();
+    let mut pending = Vec::new();
+    match cells.get(&0) {
+        Some(cell) if executable(cell) => pending.push(0),
+        Some(_) => analysis.warnings.push((Warning::ExecutesData(0), 0)),
+        None => analysis.warnings.push((Warning::JumpsOutOfProgram(0), 0)),
+    }
+
+    // new code can load more addresses, so the indirect targets are looked for until there are none
+    loop {
+        while let Some(addr) = pending.pop() {
+            if !analysis.reached.insert(addr) {
+                continue;
+            }
+            let (opcode, operand) = decode(cells[&addr].word);
+            // an extension instruction carries on with the next word
+            let (flow, target) = match isa::by_opcode(opcode) {
+                Some(instr) => (instr.flow, instr.target(addr as i32, operand)),
+                None => (Flow::Next, 0),
+            };
+
+            let mut successors = Vec::new();
+            match flow {
+                Flow::Next => successors.push((addr as i32 + 1, Edge::Next)),
+                Flow::Jump => successors.push((target, Edge::Taken)),
+                Flow::Branch => {
+                    successors.push((target, Edge::Taken));
+                    successors.push((addr as i32 + 1, Edge::Next));
+                }
+                Flow::Indirect => {
+                    indirect_jumps.insert(addr);
+                }
+                Flow::Stop => {}
+            }
+
+            for (next, edge) in successors {
+                let cell = u32::try_from(next).ok().and_then(|next| cells.get(&next));
+                match cell {
+                    Some(cell) if executable(cell) => {
+                        analysis.edges.push((addr, next as u32, edge));
+                        pending.push(next as u32);
+                    }
+                    Some(_) => analysis.warnings.push((Warning::ExecutesData(next as u32), addr)),
+                    None if edge == Edge::Next => analysis.warnings.push((Warning::FallsOffEnd, addr)),
+                    None => analysis.warnings.push((Warning::JumpsOutOfProgram(next), addr)),
+                }
+            }
+        }
+
+        if indirect_jumps.is_empty() {
+            break;
+        }
+        let targets = address_constants(cells, &analysis.reached);
+        for &from in &indirect_jumps {
+            for &to in &targets {
+                if !analysis.edges.contains(&(from, to, Edge::Indirect)) {
+                    analysis.edges.push((from, to, Edge::Indirect));
+                }
+            }
+        }
+        pending.extend(targets.into_iter().filter(|addr| !analysis.reached.contains(addr)));
+        if pending.is_empty() {
+            break;
+        }
+    }
+
+    memory_warnings(cells, &mut analysis);
+    unreachable_warnings(cells, &mut analysis);
+    analysis.warnings.sort_by_key(|&(_, addr)| addr);
+    analysis
+}
+
+// labelled code addresses that reached code loads as a value or that a data word holds
+fn address_constants(cells: &BTreeMap<u32, Cell>, reached: &BTreeSet<u32>) -> BTreeSet<u32> {
+    let loadim = isa::opcode_of("LOADIM");
+    let mut values = BTreeSet::new();
+    for (&addr, cell) in cells {
+        if reached.contains(&addr) {
+            let (opcode, operand) = decode(cell.word);
+            if opcode == loadim {
+                values.insert(operand);
+            }
+        } else if cell.kind != Kind::Code {
+            values.insert(cell.word);
+        }
+    }
+    values.into_iter()
+        .filter_map(|value| u32::try_from(value).ok())
+        .filter(|addr| cells.get(addr).is_some_and(|cell| cell.labelled && executable(cell)))
+        .collect()
+}
+
+fn memory_warnings(cells: &BTreeMap<u32, Cell>, analysis: &mut Analysis) {
+    // (instruction address, memory address, access) for every direct access
+    let mut accesses = Vec::new();
+    let mut stores_indirectly = false;
+    for &addr in &analysis.reached {
+        let (opcode, operand) = decode(cells[&addr].word);
+        let Some(instr) = isa::by_opcode(opcode) else { continue };
+        stores_indirectly |= instr.stores_indirectly();
+        if let Some(access) = instr.operand_access()
+            && operand >= 0
+            && (operand as usize) < MEMORY_SIZE
+        {
+            accesses.push((addr, operand as u32, access, instr.name));
+        }
+    }
+    let written: BTreeSet<u32> = accesses.iter().filter(|access| access.2 == Access::Write).map(|access| access.1).collect();
+
+    for &(addr, target, access, name) in &accesses {
+        let cell = cells.get(&target);
+        match access {
+            Access::Write if analysis.reached.contains(&target) || cell.is_some_and(|cell| cell.kind == Kind::Code) => {
+                analysis.warnings.push((Warning::StoreIntoCode(target), addr));
+            }
+            Access::Read if !stores_indirectly && cell.is_none() && !written.contains(&target) => {
+                analysis.warnings.push((Warning::ReadsUninitialized(target), addr));
+            }
+            _ => {}
+        }
+        if name == "DIVIDE" && !stores_indirectly && !written.contains(&target) && cell.is_none_or(|cell| cell.word == 0) {
+            analysis.warnings.push((Warning::DivisionByZero(target), addr));
+        }
+    }
+}
+
+fn unreachable_warnings(cells: &BTreeMap<u32, Cell>, analysis: &mut Analysis) {
+    // words an .sml program reads or writes are its data
+    let mut referenced = BTreeSet::new();
+    for &addr in &analysis.reached {
+        let (opcode, operand) = decode(cells[&addr].word);
+        if isa::by_opcode(opcode).is_some_and(|instr| instr.operand_access().is_some()) {
+            referenced.insert(operand);
+        }
+    }
+    let hlt = isa::encode(isa::opcode_of("HALT"), 0);
+
+    let mut previous_unreachable = false;
+    let mut previous_addr = None;
+    for (&addr, cell) in cells {
+        let unreachable = !analysis.reached.contains(&addr)
+            && cell.line_num.is_some()
+            && match cell.kind {
+                Kind::Code => true,
+                Kind::Data => false,
+                // a stray hlt or 0 is no sign of lost code
+                Kind::Unknown => executable(cell) && cell.word != 0 && cell.word != hlt && !referenced.contains(&(addr as i32)),
+            };
+        let continues = previous_unreachable && previous_addr == addr.checked_sub(1);
+        if unreachable && !continues {
+            analysis.warnings.push((Warning::UnreachableCode, addr));
+        }
+        previous_unreachable = unreachable;
+        previous_addr = Some(addr);
+    }
+}
+
+// the reached code in blocks that are only entered at the top, as a Graphviz digraph
+pub fn to_dot(analysis: &Analysis, cells: &BTreeMap<u32, Cell>, labels: &BTreeMap<String, u32>) -> String {
+    let image_len = cells.keys().next_back().map_or(0, |&addr| addr as usize + 1);
+
+    // a block starts at 0, at everything something jumps to and after every jump
+    let mut leaders = BTreeSet::from([0]);
+    for &(from, to, edge) in &analysis.edges {
+        if edge != Edge::Next {
+            leaders.insert(to);
+            leaders.insert(from + 1);
+        }
+    }
+    for &addr in &analysis.reached {
+        let (opcode, _) = decode(cells[&addr].word);
+        if isa::by_opcode(opcode).is_some_and(|instr| instr.flow != Flow::Next) {
+            leaders.insert(addr + 1);
+        }
+    }
+
+    // first address of the block each reached address is in
+    let mut block_of = BTreeMap::new();
+    let mut blocks: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    let mut current = None;
+    for &addr in &analysis.reached {
+        if leaders.contains(&addr) || current.is_none_or(|(_, last)| last + 1 != addr) {
+            current = Some((addr, addr));
+        }
+        let (start, _) = current.expect("set above");
+        current = Some((start, addr));
+        block_of.insert(addr, start);
+        blocks.entry(start).or_default().push(addr);
+    }
+
+    let mut names: BTreeMap<u32, Vec<&str>> = BTreeMap::new();
+    for (name, &addr) in labels {
+        names.entry(addr).or_default().push(name);
+    }
+
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+    for (&start, addrs) in &blocks {
+        let mut label = String::new();
+        if let Some(names) = names.get(&start) {
+            label.push_str(&format!("{}:\\l", names.join(", ")));
+        }
+        for &addr in addrs {
+            let word = cells[&addr].word;
+            let (opcode, operand) = decode(word);
+            // a branch out of the program has no label to go to
+            let text = instruction_text(word, addr as usize, image_len)
+                .or_else(|| isa::by_opcode(opcode).map(|instr| format!("{} {}", instr.mnemonic, operand)))
+                .unwrap_or_else(|| format!("dw {}", format_word(word)));
+            label.push_str(&format!("{:04}  {}\\l", addr, text));
+        }
+        out.push_str(&format!("    b{} [label=\"{}\"];\n", start, label.replace('"', "\\\"")));
+    }
+    for &(from, to, edge) in &analysis.edges {
+        let (from_block, to_block) = (block_of[&from], block_of[&to]);
+        // edges inside a block are its straight line
+        if edge == Edge::Next && from_block == to_block {
+            continue;
+        }
+        let style = match edge {
+            Edge::Next => "",
+            Edge::Taken => " [label=\"taken\"]",
+            Edge::Indirect => " [style=dashed]",
+        };
+        out.push_str(&format!("    b{} -> b{}{};\n", from_block, to_block, style));
+    }
+    out.push_str("}\n");
+    out
+}
@@ -1,16 +1,59 @@
-use std::{collections::HashMap, string};
+use std::path::Path;
+
+use isa::Arg;
+
+use crate::conditional::{parse_define, select, Defines};
+use crate::encoder::encode_data;
+use crate::extensions::Extensions;
+use crate::include::{self, Sources};
+use crate::listing::listing;
+use crate::layout::{memory_map_report, Image, MemoryRegion, Operand, PageBound, Program, Section, PAGE_SIZE};
+use crate::parser::{parse, ParseError, StatementKind, Term};
+use crate::locals;
+use crate::procs::expand;
+use crate::optimize;
+use crate::structured;
 
 pub struct Asmbler;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AsmblerError {
     InvalidInstruction,
     InvalidOperand,
     SyntaxError,
     LabelAlreadyDefined,
-    MACROAlreadyDefined,
-    MACRONotDefined,
     MemoryRequestOutOfBounds,
+    OpcodeOutOfRange { opcode: u32, max: u32 },
+    OperandOutOfRange { value: i32, min: i32, max: i32 },
+    WordOutOfRange { value: i32, min: i32, max: i32 },
+    NegativeZero,
+    UndefinedLabel(String),
+    AddressConflict(u32),
+    EntryAlreadyDefined,
+    InvalidNumber(String),
+    UnexpectedCharacter(char),
+    UnterminatedLiteral,
+    InvalidEscape,
+    OpcodeAlreadyDefined(u32),
+    MnemonicAlreadyDefined(String),
+    NestedProcedure,
+    NotInProcedure,
+    UnterminatedProcedure,
+    UndefinedProcedure(String),
+    UndefinedName(String),
+    // -DNAME where NAME is also a label
+    DefineIsLabel(String),
+    UnmatchedConditional,
+    UnterminatedConditional,
+    // .endw without a .while, .endif after .while ...
+    UnmatchedBlock,
+    UnterminatedBlock,
+    IncludeNotFound(String),
+    IncludeCycle(String),
+    // .org, dw [addr] and pages(label) need the final address, which an object file does not have
+    NotRelocatable,
+    // two object files export the same label
+    GlobalAlreadyDefined(String),
 }
 
 #[derive(PartialEq)]
@@ -19,636 +62,419 @@ pub struct AssembledContents {
     pub results: String,
     pub flags: Vec<String>,
     pub errors: Vec<(AsmblerError, String, usize)>,
+    // flags that could not be used, with the flag
+    pub flag_errors: Vec<(AsmblerError, String)>,
+    pub memory_map: Vec<MemoryRegion>,
+    pub image: Option<Image>,
+    // the file each line came from once .include lines are filled in
+    pub sources: Sources,
+    // what --optimize changed, (what, line, line number)
+    pub optimizations: Vec<(String, String, usize)>,
+    // the input with .include files filled in, line numbers count lines of it
+    pub text: String,
 }
 
 impl AssembledContents {
     pub fn has_errors(&self) -> bool {
-        !self.errors.is_empty()
+        !self.errors.is_empty() || !self.flag_errors.is_empty()
     }
 
     pub fn print_errors(&self) {
+        for (error, flag) in &self.flag_errors {
+            println!("Error in flag {}: {:?}", flag, error);
+        }
         for (error, line, line_num) in &self.errors {
-            println!("Error on line {}: {:?} - {}", line_num + 1, error, line);
+            if self.sources.files.len() > 1 {
+                let (file, file_line) = self.sources.location(*line_num);
+                println!("Error on line {} of {}: {:?} - {}", file_line + 1, file, error, line);
+            } else {
+                println!("Error on line {}: {:?} - {}", line_num + 1, error, line);
+            }
         }
     }
+
+    pub fn print_optimizations(&self) {
+        for (change, line, line_num) in &self.optimizations {
+            if self.sources.files.len() > 1 {
+                let (file, file_line) = self.sources.location(*line_num);
+                println!("Optimized line {} of {}: {} - {}", file_line + 1, file, change, line);
+            } else {
+                println!("Optimized line {}: {} - {}", line_num + 1, change, line);
+            }
+        }
+    }
+
+    // "line 3" or "line 3 of lib.asml" and the source line, for diagnostics about a line
+    pub fn location(&self, line_num: usize) -> (String, String) {
+        let line = self.text.split('\n').nth(line_num).unwrap_or("").trim().to_string();
+        if self.sources.files.len() > 1 {
+            let (file, file_line) = self.sources.location(line_num);
+            (format!("line {} of {}", file_line + 1, file), line)
+        } else {
+            (format!("line {}", line_num + 1), line)
+        }
+    }
+
+    pub fn memory_map_report(&self) -> String {
+        memory_map_report(&self.memory_map)
+    }
 }
 
 const MAX_MEM_ADDR: u32 = 9999;
 
 impl Asmbler {
-    pub fn assemble(contents: String, _flags: Vec<String>) -> AssembledContents {
+    // flags:
+    // --opcode=name opcode shape - the same as an .opcode line at the top of the file, an ISA profile is a list of these
+    // --no-implicit-hlt - do not end the code with a hlt
+    // -DNAME=value, -DNAME - a name for .ifdef, .ifndef and .if, and a number for operands
+    // --source=path - where the input was read from, .include paths are relative to it
+    // --object - write an object file for the linker instead of an image
+    // --pic - jmp, jlz and jez to a label become jmpr, jlzr and jezr
+    // --optimize - the peephole pass in optimize.rs, what it changes is in optimizations
+    pub fn assemble(contents: String, flags: Vec<String>) -> AssembledContents {
+
+        let source = flags.iter().find_map(|flag| flag.strip_prefix("--source=")).map(Path::new);
+        let name = source.and_then(|path| path.file_name()).map_or("input".to_string(), |name| name.to_string_lossy().to_string());
+        let dir = source.and_then(|path| path.parent()).unwrap_or(Path::new(""));
+        let (text, sources, include_errors) = include::expand(&name, &contents, dir);
+        let object = flags.iter().any(|flag| flag == "--object");
+        let pic = flags.iter().any(|flag| flag == "--pic");
+        let optimize = flags.iter().any(|flag| flag == "--optimize");
 
         let mut result = AssembledContents {
             contents: contents.clone(),
             results: String::new(),
-            flags: _flags,
+            flags,
             errors: Vec::new(),
+            flag_errors: Vec::new(),
+            memory_map: Vec::new(),
+            image: None,
+            sources,
+            optimizations: Vec::new(),
+            text: text.clone(),
         };
 
-        let mut labels: HashMap<String, usize> = HashMap::new();
-        // (label_name, line_index, opcode)
-        let mut unresolved_labels: Vec<(String, usize, u32)> = Vec::new();
-
-        let mut defined_words: HashMap<u32, i32> = HashMap::new();
-
-        let mut result_lines: Vec<String> = Vec::new();
-
-        let lines = contents.split('\n');
-
-        // filter out empty lines and trim whitespace
-        let mut lines: Vec<&str> = lines
-            .filter(|line| !line.trim().is_empty())
-            .map(|line| line.trim())
-            .collect();
-
-        for i in 0..lines.len() {
-            let line = lines[i];
-            // skip comments
-            if line.starts_with(";") { continue; }
-
-            // read the instruction
-            let parts: Vec<&str> = line.split_whitespace().collect();
-
-            match parts[0] {
-                "read" => {
-
-                    if parts.len() != 2 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let dest = parts[1];
-
-                    if is_like_mem_addr(dest) {
-                        let mem_addr = parse_mem_addr(dest);
-                        if mem_addr.is_err() {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                        let mem_addr = mem_addr.unwrap();
-                        if mem_addr > MAX_MEM_ADDR {
-                            result.errors.push((AsmblerError::MemoryRequestOutOfBounds, line.to_string(), i));
-                            continue;
-                        }
-                        result_lines.push(format_instruction(10, mem_addr as i32));
-                    } else {
-                        result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                    }
-
-                }
-                "write" => {
-                    if parts.len() != 2 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let src = parts[1];
-
-                    if is_like_mem_addr(src) {
-                        let mem_addr = parse_mem_addr(src);
-                        if mem_addr.is_err() {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                        let mem_addr = mem_addr.unwrap();
-                        if mem_addr > MAX_MEM_ADDR {
-                            result.errors.push((AsmblerError::MemoryRequestOutOfBounds, line.to_string(), i));
-                            continue;
-                        }
-                        result_lines.push(format_instruction(11, mem_addr as i32));
-                    } else {
-                        result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                    }
-
-                }
-                // writec acc
-                "writec" => {
-                    if parts.len() != 1 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    result_lines.push(format_instruction(12, 0));
-                }
-                "mov" => {
-                    if parts.len() != 3 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-                    let dest = parts[1];
-                    let src = parts[2];
-
-                    match (dest, src) {
+        let mut program = Program::new(&text);
+        for (error, line_num) in include_errors {
+            program.error(error, line_num);
+        }
 
-                        //mov acc [idx]
-                        //- LOADIDX 23
-                        ("acc", "[idx]") => {
-                            result_lines.push(format_instruction(23, 0));
-                        }
-                        //mov acc [lit]
-                        //- LOAD 20
-                        ("acc", src) if is_like_mem_addr(src) => {
-                            match parse_mem_addr(src) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(20, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
-                        //mov acc lit
-                        //- LOADIM 21
-                        ("acc", src) if is_like_lit(src) => {
-                            match parse_lit(src) {
-                                Ok(addr) => {
-                                    result_lines.push(format_instruction(21, addr));
-                                }
-                                Err(e) => {
-                                    result.errors.push((e, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
-                        //mov idx [lit]
-                        //- LOADX 22
-                        ("idx", src) if is_like_mem_addr(src) => {
-                            match parse_mem_addr(src) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(22, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
-                        //mov [idx] acc
-                        //- STOREIDX 26
-                        ("[idx]", "acc") => {
-                            result_lines.push(format_instruction(26, 0));
-                        }
-                        //mov [lit] acc
-                        //- STORE 25
-                        (dest, "acc") if is_like_mem_addr(dest) => {
-                            match parse_mem_addr(dest) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(25, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
-                        
-                        _ => {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                    }
+        let mut extensions = Extensions::default();
+        let mut defines = Defines::new();
+        for flag in &result.flags {
+            if let Some(spec) = flag.strip_prefix("--opcode=")
+                && let Err(e) = extensions.define_from_flag(spec)
+            {
+                result.flag_errors.push((e, flag.clone()));
+            }
+            match parse_define(flag) {
+                Some(Ok((name, value))) => {
+                    defines.insert(name, value);
                 }
-                "add" => {
-
-                    if parts.len() != 3 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let dest = parts[1];
-                    let src = parts[2];
-
-                    match (dest, src) {
-
-                        // add acc [idx]
-                        // - ADDX 31
-                        ("acc", "[idx]") => {
-                            result_lines.push(format_instruction(31, 0));
-                        }
+                Some(Err(e)) => result.flag_errors.push((e, flag.clone())),
+                None => {}
+            }
+        }
 
-                        // add acc [lit]
-                        // - ADD 30
-                        ("acc", src) if is_like_mem_addr(src) => {
-                            match parse_mem_addr(src) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(30, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
+        let statements = expand(structured::expand(locals::resolve(select(parse(&text), &defines))));
+        let statements = if optimize {
+            let (statements, changes) = optimize::optimize(statements);
+            let lines: Vec<&str> = text.split('\n').collect();
+            result.optimizations = changes.into_iter()
+                .map(|change| (change.description, lines.get(change.line_num).unwrap_or(&"").trim().to_string(), change.line_num))
+                .collect();
+            statements
+        } else {
+            statements
+        };
 
-                        _ => {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                        
+        for statement in statements {
+            let statement = match statement {
+                Ok(statement) => statement,
+                Err(ParseError { error, line_num, labels }) => {
+                    // the labels still exist so later uses of them are not reported as well
+                    for label in &labels {
+                        program.define_label(label, line_num);
                     }
+                    program.error(error, line_num);
+                    continue;
                 }
-                "sub" => {
-
-                    if parts.len() != 3 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let dest = parts[1];
-                    let src = parts[2];
-
-                    match (dest, src) {
+            };
 
-                        // sub acc [idx]
-                        // - SUBX 33
-                        ("acc", "[idx]") => {
-                            result_lines.push(format_instruction(33, 0));
-                        }
-
-                        // sub acc [lit]
-                        // - SUB 32
-                        ("acc", src) if is_like_mem_addr(src) => {
-                            match parse_mem_addr(src) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(32, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
-
-                        _ => {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                        
-                    }
+            let i = statement.line_num;
+            for label in &statement.labels {
+                program.define_label(label, i);
+            }
 
+            match &statement.kind {
+                None => {}
+                // an object file can be placed anywhere
+                Some(StatementKind::Directive { name, .. }) if object && name == ".org" => {
+                    program.error(AsmblerError::NotRelocatable, i);
                 }
-                "mul" => {
-
-                    if parts.len() != 3 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let dest = parts[1];
-                    let src = parts[2];
-
-                    match (dest, src) {
-
-                        // mul acc [idx]
-                        // - MULX 37
-                        ("acc", "[idx]") => {
-                            result_lines.push(format_instruction(37, 0));
-                        }
-
-                        // mul acc [lit]
-                        // - MUL 36
-                        ("acc", src) if is_like_mem_addr(src) => {
-                            match parse_mem_addr(src) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(36, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
-                            }
-                        }
-
-                        _ => {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                    }
+                Some(StatementKind::Directive { name, args }) => {
+                    assemble_directive(&mut program, &mut extensions, name, args, i);
                 }
-                "div" => {
-
-                    if parts.len() != 3 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let dest = parts[1];
-                    let src = parts[2];
-
-                    match (dest, src) {
-
-                        // div acc [idx]
-                        // - DIVX 35
-                        ("acc", "[idx]") => {
-                            result_lines.push(format_instruction(35, 0));
-                        }
-
-                        // div acc [lit]
-                        // - DIV 34
-                        ("acc", src) if is_like_mem_addr(src) => {
-                            match parse_mem_addr(src) {
-                                Ok(addr) if addr <= MAX_MEM_ADDR => {
-                                    result_lines.push(format_instruction(34, addr as i32));
-                                }
-                                _ => {
-                                    result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                                    continue;
-                                }
+                Some(StatementKind::Instruction { mnemonic, operands }) if mnemonic == "dw" => {
+                    match operands.as_slice() {
+                        // dw [lit] lit - a word at a fixed address
+                        [Term::Memory(_), Term::Number(_)] if object => program.error(AsmblerError::NotRelocatable, i),
+                        [Term::Memory(dest), Term::Number(lit)] => match parse_fixed_addr(dest) {
+                            Ok(addr) => program.define_word_at(addr, *lit, i),
+                            Err(e) => program.error(e, i),
+                        },
+                        // dw name value - a word with a label of its own, a bare label as the value is its address
+                        [Term::Symbol(name), value] => {
+                            program.define_label(name, i);
+                            match value {
+                                Term::Symbol(label) => program.emit_address(label, i),
+                                _ => emit_data_term(&mut program, value, i),
                             }
                         }
-
-                        _ => {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                    }
-                }
-                "inc" => {
-
-                    if parts.len() != 1 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-                    result_lines.push(format_instruction(38, 0));
-
-                }
-                "dec" => {
-
-                    if parts.len() != 1 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-                    result_lines.push(format_instruction(39, 0));
-
-                }
-                // labels
-                s if s.ends_with(':') => {
-
-                    let label_name = &s[..s.len()-1];
-                    if labels.contains_key(label_name) {
-                        result.errors.push((AsmblerError::LabelAlreadyDefined, line.to_string(), i));
-                        continue;
-                    }
-                    labels.insert(label_name.to_string(), result_lines.len());
-
-                }
-                "jmp" => {
-                    if parts.len() != 2 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let label_name= parts[1];
-
-                    let label_addr = match labels.get(label_name) {
-                        Some(&addr) => addr,
-                        None => {
-                            result_lines.push(String::new());
-                            unresolved_labels.push((label_name.to_string(), result_lines.len() - 1, 40));
-                            continue;
-                        }
-                    };
-
-                    if label_addr > MAX_MEM_ADDR as usize {
-                        result.errors.push((AsmblerError::MemoryRequestOutOfBounds, line.to_string(), i));
-                        continue;
+                        // dw label - the address of the label
+                        [Term::Symbol(label)] => program.emit_address(label, i),
+                        [] => program.error(AsmblerError::SyntaxError, i),
+                        // dw lit, dw &label - a word at the current location, several make a table
+                        values => values.iter().for_each(|value| emit_data_term(&mut program, value, i)),
                     }
-
-                    result_lines.push(format_instruction(40, label_addr as i32));
                 }
-                "jlz" => {
-
-                    if parts.len() != 2 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let label_name= parts[1];
-
-                    let label_addr = match labels.get(label_name) {
-                        Some(&addr) => addr,
-                        None => {
-                            result_lines.push(String::new());
-                            unresolved_labels.push((label_name.to_string(), result_lines.len() - 1, 41));
-                            continue;
-                        }
+                // everything else is an instruction from the ISA
+                Some(StatementKind::Instruction { mnemonic, operands }) => {
+                    let mnemonic = match (mnemonic.as_str(), operands.as_slice()) {
+                        ("jmp" | "jlz" | "jez", [Term::Symbol(_)]) if pic => format!("{}r", mnemonic),
+                        _ => mnemonic.clone(),
                     };
-
-                    if label_addr > MAX_MEM_ADDR as usize {
-                        result.errors.push((AsmblerError::MemoryRequestOutOfBounds, line.to_string(), i));
-                        continue;
-                    }
-
-                    result_lines.push(format_instruction(41, label_addr as i32));
+                    assemble_instruction(&mut program, &extensions, &mnemonic, operands, i);
                 }
-                "jez" => {
-
-                    if parts.len() != 2 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let label_name= parts[1];
-
-                    let label_addr = match labels.get(label_name) {
-                        Some(&addr) => addr,
-                        None => {
-                            result_lines.push(String::new());
-                            unresolved_labels.push((label_name.to_string(), result_lines.len() - 1, 42));
-                            continue;
-                        }
-                    };
-
-                    if label_addr > MAX_MEM_ADDR as usize {
-                        result.errors.push((AsmblerError::MemoryRequestOutOfBounds, line.to_string(), i));
-                        continue;
-                    }
-
-                    result_lines.push(format_instruction(42, label_addr as i32));
-
-                }
-                "swap" => {
-                    if parts.len() != 1 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-                    result_lines.push(format_instruction(43, 0));
-                }
-                "hlt" => {
-                    if parts.len() != 1 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-                    result_lines.push(format_instruction(45, 0));
-                }
-                "dw" => {
-                    if parts.len() != 3 {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
-
-                    let dest = parts[1];
-                    let src = parts[2];
+            }
+        }
 
-                    if !is_like_mem_addr(dest) {
-                        result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                        continue;
-                    }
+        if object {
+            match program.link_object(&result.sources) {
+                Ok(object) => result.contents = object.to_text(),
+                Err(errors) => result.errors = errors,
+            }
+            return result;
+        }
 
-                    let mem_addr = parse_mem_addr(dest);
-                    if mem_addr.is_err() {
-                        result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                        continue;
-                    }
-                    let mem_addr = mem_addr.unwrap();
-                    if mem_addr > MAX_MEM_ADDR {
-                        result.errors.push((AsmblerError::MemoryRequestOutOfBounds, line.to_string(), i));
-                        continue;
-                    }
+        let implicit_hlt = !result.flags.iter().any(|flag| flag == "--no-implicit-hlt");
+        match program.link(implicit_hlt) {
+            Ok(image) => {
+                result.contents = image.to_sml();
+                result.results = listing(&image, &text, &extensions);
+                result.memory_map = image.regions.clone();
+                result.image = Some(image);
+            }
+            Err(errors) => {
+                result.errors = errors;
+            }
+        }
 
-                    let lit = parse_lit(src);
-                    if lit.is_err() {
-                        result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                        continue;
-                    }
-                    let lit = lit.unwrap();
+        result
+    }
+}
 
-                    defined_words.insert(mem_addr, lit);
-                }
-                "assert" => {
-                    if parts.len() == 1 {
-                        result_lines.push(format_instruction(1, 0));
-                    } else if parts.len() == 2 {
-                        let lit = parse_lit(parts[1]);
-                        if lit.is_err() {
-                            result.errors.push((AsmblerError::InvalidOperand, line.to_string(), i));
-                            continue;
-                        }
-                        let lit = lit.unwrap();
-                        result_lines.push(format_instruction(1, lit));
-                    } else {
-                        result.errors.push((AsmblerError::SyntaxError, line.to_string(), i));
-                        continue;
-                    }
+fn assemble_directive(program: &mut Program, extensions: &mut Extensions, name: &str, args: &[Term], i: usize) {
+    match (name, args) {
+        // .opcode name opcode shape - a mnemonic for an instruction the simulator's host adds
+        (".opcode", _) => {
+            if let Err(e) = extensions.define(args) {
+                program.error(e, i);
+            }
+        }
+        (".code", []) => program.set_section(Section::Code),
+        (".data", []) => program.set_section(Section::Data),
+        // .org lit - place what follows in this section from lit
+        (".org", [addr]) => match parse_addr(addr) {
+            Ok(addr) => program.org(addr),
+            Err(e) => program.error(e, i),
+        },
+        // .align - move to the next page
+        (".align", []) => program.align(PAGE_SIZE),
+        // .align lit - move to the next multiple of lit
+        (".align", [boundary]) => match parse_addr(boundary) {
+            Ok(0) => program.error(AsmblerError::InvalidOperand, i),
+            Ok(n) => program.align(n),
+            Err(e) => program.error(e, i),
+        },
+        // .entry label - where execution starts, a jmp is placed at address 0 when it is not 0
+        (".entry", [Term::Symbol(label)]) => program.set_entry(label, i),
+        // .include "file" - the file's lines were put after this one before parsing
+        (".include", [Term::Str(_)]) => {}
+        // .global label - a label other object files can use
+        (".global", [Term::Symbol(label)]) => program.export(label, i),
+        // .extern label - a label from another object file
+        (".extern", [Term::Symbol(label)]) => program.import(label, i),
+        // .ascii "text" - one word per character holding its ascii code
+        // .asciiz "text" - the same followed by a 0 word to mark the end
+        (".ascii" | ".asciiz", [Term::Str(text)]) => {
+            if !text.is_ascii() {
+                program.error(AsmblerError::InvalidOperand, i);
+                return;
+            }
+            for c in text.chars() {
+                program.emit_data(c as i32, i);
+            }
+            if name == ".asciiz" {
+                program.emit_data(0, i);
+            }
+        }
+        // .fill n value - n words of value
+        (".fill", [count, Term::Number(value)]) => match parse_addr(count).and_then(|n| encode_data(*value).map(|_| n)) {
+            Ok(n) => (0..n).for_each(|_| program.emit_data(*value, i)),
+            Err(e) => program.error(e, i),
+        },
+        // .space n - n words of 0
+        (".space", [count]) => match parse_addr(count) {
+            Ok(n) => (0..n).for_each(|_| program.emit_data(0, i)),
+            Err(e) => program.error(e, i),
+        },
+        (".code" | ".data" | ".org" | ".align" | ".entry" | ".include" | ".global" | ".extern" | ".ascii" | ".asciiz" | ".fill" | ".space", _) => {
+            program.error(AsmblerError::SyntaxError, i)
+        }
+        _ => program.error(AsmblerError::InvalidInstruction, i),
+    }
+}
 
+// finds the instruction whose written form matches the operands and emits it
+fn assemble_instruction(program: &mut Program, extensions: &Extensions, mnemonic: &str, args: &[Term], line_num: usize) {
+
+    let mut known_mnemonic = false;
+    let mut right_count = false;
+
+    // (opcode, form) for every way the mnemonic can be written, the ISA's first
+    let forms = isa::by_mnemonic(mnemonic)
+        .flat_map(|instr| instr.forms.iter().map(move |form| (instr.opcode, *form)))
+        .chain(extensions.by_mnemonic(mnemonic).map(|ext| (ext.opcode, ext.form.as_slice())));
+
+    for (opcode, form) in forms {
+        known_mnemonic = true;
+        if form.len() == args.len() {
+            right_count = true;
+            match match_form(form, args) {
+                Ok(Some(operand)) => {
+                    program.emit(opcode, operand, line_num);
+                    return;
                 }
-                _ => {
-                    // handle unknown instructions
-                    result.errors.push((AsmblerError::InvalidInstruction, line.to_string(), i));
+                Ok(None) => {}
+                Err(e) => {
+                    program.error(e, line_num);
+                    return;
                 }
             }
-
         }
+    }
 
-        result_lines.push(format_instruction(45, 0)); // always end with hlt
+    let error = if !known_mnemonic {
+        AsmblerError::InvalidInstruction
+    } else if !right_count {
+        AsmblerError::SyntaxError
+    } else {
+        AsmblerError::InvalidOperand
+    };
+    program.error(error, line_num);
+}
 
-        // resolve unresolved labels
-        for (label_name, line_index, opcode) in unresolved_labels {
-            match labels.get(&label_name) {
-                Some(&addr) => {
-                    if addr > MAX_MEM_ADDR as usize {
-                        result.errors.push((AsmblerError::MemoryRequestOutOfBounds, format!("Unresolved label: {}", label_name), 0));
-                        continue;
-                    }
-                    result_lines[line_index] = format_instruction(opcode, addr as i32);
+// the operand when the terms have the shape of the form, None when it is a different form
+fn match_form(form: &[Arg], args: &[Term]) -> Result<Option<Operand>, AsmblerError> {
+    let mut operand = Operand::Lit(0);
+
+    for (arg, term) in form.iter().zip(args) {
+        match (arg, term) {
+            (Arg::Acc, Term::Register(reg)) if reg == "acc" => {}
+            (Arg::Idx, Term::Register(reg)) if reg == "idx" => {}
+            (Arg::AtIdx, Term::Memory(inner)) if matches!(inner.as_ref(), Term::Register(reg) if reg == "idx") => {}
+            (Arg::Mem, Term::Memory(inner)) if matches!(inner.as_ref(), Term::Number(_) | Term::Symbol(_)) => {
+                operand = parse_mem_addr(inner)?;
+            }
+            (Arg::IdxOffset, Term::Memory(inner)) => match inner.as_ref() {
+                Term::Offset(base, offset) if matches!(base.as_ref(), Term::Register(reg) if reg == "idx") => {
+                    operand = match offset.as_ref() {
+                        Term::Number(lit) => Operand::Lit(*lit),
+                        Term::Symbol(label) => Operand::Label(label.clone()),
+                        _ => return Err(AsmblerError::InvalidOperand),
+                    };
+                }
+                _ => return Ok(None),
+            },
+            (Arg::Indirect, Term::Memory(inner)) => match inner.as_ref() {
+                Term::Memory(pointer) if matches!(pointer.as_ref(), Term::Number(_) | Term::Symbol(_)) => {
+                    operand = parse_mem_addr(pointer)?;
                 }
-                None => {
-                    result.errors.push((AsmblerError::InvalidOperand, format!("Unresolved label: {}", label_name), 0));
+                _ => return Ok(None),
+            },
+            (Arg::Imm, Term::Number(lit)) => operand = Operand::Lit(*lit),
+            (Arg::Imm, Term::AddressOf(inner)) => match inner.as_ref() {
+                Term::Symbol(label) => operand = Operand::Label(label.clone()),
+                _ => return Err(AsmblerError::InvalidOperand),
+            },
+            (Arg::Target, Term::Symbol(label)) => operand = Operand::Label(label.clone()),
+            (Arg::RelTarget, Term::Symbol(label)) => operand = Operand::Relative(label.clone()),
+            // hlt 3, hlt 0..3, hlt pages(buf), hlt 0..pages(buf)
+            (Arg::Pages, Term::Range(first, last)) => {
+                let (first, last) = (page_bound(first)?, page_bound(last)?);
+                if let (PageBound::Page(first), PageBound::Page(last)) = (&first, &last)
+                    && first > last
+                {
+                    return Err(AsmblerError::InvalidOperand);
                 }
+                operand = Operand::Pages(first, last);
             }
-        }
-
-        // insert defined words into result lines
-        for (&addr, &value) in &defined_words {
-            if addr as usize >= result_lines.len() {
-                result_lines.resize(addr as usize + 1, "+000000".to_string());
+            (Arg::Pages, Term::Number(_) | Term::Call(..)) => {
+                let bound = page_bound(term)?;
+                operand = Operand::Pages(bound.clone(), bound);
             }
-            result_lines[addr as usize] = format_instruction(0, value);
+            _ => return Ok(None),
         }
-
-        result.contents = result_lines.join("\n");
-        return result;
     }
-}
 
-fn is_like_mem_addr(text: &str) -> bool {
-    text.starts_with('[') && text.ends_with(']') && text[1..text.len()-1].parse::<u32>().is_ok()
+    Ok(Some(operand))
 }
 
-fn is_like_lit(text: &str) -> bool {
-    text.parse::<i32>().is_ok()
-}
-
-fn parse_lit(text: &str) -> Result<i32, AsmblerError> {
-    match text.parse::<i32>() {
-        Ok(lit) => Ok(lit),
-        Err(_) => Err(AsmblerError::InvalidOperand),
+// lit or &label as a data word
+fn emit_data_term(program: &mut Program, term: &Term, i: usize) {
+    match term {
+        Term::Number(lit) => program.emit_data(*lit, i),
+        Term::AddressOf(inner) => match inner.as_ref() {
+            Term::Symbol(label) => program.emit_address(label, i),
+            _ => program.error(AsmblerError::InvalidOperand, i),
+        },
+        _ => program.error(AsmblerError::InvalidOperand, i),
     }
 }
 
-fn parse_mem_addr(text: &str) -> Result<u32, AsmblerError> {
-    if !is_like_mem_addr(text) {
-        return Err(AsmblerError::SyntaxError);
-    }
-    let inner = &text[1..text.len()-1];
-    match inner.parse::<u32>() {
-        Ok(addr) => Ok(addr),
-        Err(_) => Err(AsmblerError::InvalidOperand),
+fn page_bound(term: &Term) -> Result<PageBound, AsmblerError> {
+    match term {
+        Term::Number(page) if (0..isa::MEMORY_PAGES as i32).contains(page) => Ok(PageBound::Page(*page as u32)),
+        Term::Number(_) => Err(AsmblerError::MemoryRequestOutOfBounds),
+        Term::Call(name, arg) if name == "pages" => match arg.as_ref() {
+            Term::Symbol(label) => Ok(PageBound::PageOf(label.clone())),
+            _ => Err(AsmblerError::InvalidOperand),
+        },
+        _ => Err(AsmblerError::InvalidOperand),
     }
 }
 
-fn format_instruction(opcode: u32, operand: i32) -> String {
-    let sign = if operand < 0 { "-" } else { "+" };
-    format!("{}{:0>2}{:0>4}", sign, opcode, operand.abs())
-}
-
-struct Claim {
-    pub attacks: Vec<Claim>,
-    pub defends: Vec<Claim>,
-    pub accepted: bool,
-    pub minAgree: usize,
-}
-
-fn canAccept(claim: Claim) -> bool {
-
-    // there are no attacks
-    if claim.attacks.is_empty() {
-        return true;
+fn parse_addr(term: &Term) -> Result<u32, AsmblerError> {
+    match term {
+        Term::Number(addr) if (0..=MAX_MEM_ADDR as i32).contains(addr) => Ok(*addr as u32),
+        Term::Number(_) => Err(AsmblerError::MemoryRequestOutOfBounds),
+        _ => Err(AsmblerError::InvalidOperand),
     }
-
-    // I argree with enough defenses
-
-    return true;
-}
-
-fn isValidReson(reason: &str) -> bool {
-    return !reason.trim().is_empty();
 }
 
-fn canReject(claim: Claim, reason: &str) -> bool {
-
-    // there are no attacks
-    if claim.attacks.is_empty() {
-        return false;
-    }
-
-    // I disargree with enough defenses
-
-    // I must agree with at least one attack
-    let mut agrees_with_attack = false;
-    for sub_claim in claim.attacks {
-        if sub_claim.accepted {
-            agrees_with_attack = true;
-            break;
-        }
+// what is inside [lit] or [label]
+fn parse_mem_addr(inner: &Term) -> Result<Operand, AsmblerError> {
+    match inner {
+        Term::Symbol(label) => Ok(Operand::Label(label.clone())),
+        _ => parse_addr(inner).map(|addr| Operand::Lit(addr as i32)),
     }
+}
 
-    // I must have a valid reason
-    if !isValidReson(reason) {
-        return false;
+// only a literal address, for dw [lit] lit
+fn parse_fixed_addr(inner: &Term) -> Result<u32, AsmblerError> {
+    match parse_mem_addr(inner)? {
+        Operand::Lit(addr) => Ok(addr as u32),
+        _ => Err(AsmblerError::InvalidOperand),
     }
-
-    return true;
-
-}
\ No newline at end of file
+}
@@ -0,0 +1,258 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::asmbler::AsmblerError;
+use crate::lexer::parse_number;
+use crate::parser::{is_label_name, ParseError, Statement, StatementKind, Term};
+
+// .ifdef NAME / .ifndef NAME / .if expr, .else, .endif, chosen before anything else sees the lines
+//
+// names come from -DNAME=value flags, -DNAME on its own is 1, values are numbers
+// .if takes a number or a name, true when it is not 0, or a comparison: .if LEVEL >= 2
+// a defined name can also be used wherever a number can, ie .org BUFFER_BASE, so one that is
+// also a label is refused, jmp loop would otherwise quietly become jmp 5 with -Dloop=5
+// an .if that tests acc or idx is checked when the program runs, it and its .else and .endif
+// are left for the structured control flow pass
+
+pub type Defines = HashMap<String, i32>;
+
+struct Branch {
+    // the lines around the .if are kept
+    parent_active: bool,
+    // the lines of this branch are kept
+    active: bool,
+    // the condition was true, so the .else branch is dropped
+    taken: bool,
+    seen_else: bool,
+    line_num: usize,
+    // .if acc < 0, kept for the structured pass, it keeps or drops nothing itself
+    runtime: bool,
+}
+
+pub fn select(statements: Vec<Result<Statement, ParseError>>, defines: &Defines) -> Vec<Result<Statement, ParseError>> {
+    let mut stack: Vec<Branch> = Vec::new();
+    let mut out = Vec::new();
+
+    // a name that is both is reported where the label is and left alone everywhere else
+    let labels: HashSet<String> = statements.iter().flatten().flat_map(labels_defined).cloned().collect();
+    let substitutes: Defines = defines.iter()
+        .filter(|&(name, _)| !labels.contains(name))
+        .map(|(name, &value)| (name.clone(), value))
+        .collect();
+
+    for statement in statements {
+        let active = stack.last().is_none_or(|branch| branch.active);
+
+        let (line_num, labels, name, args) = match &statement {
+            Ok(Statement { line_num, labels, kind: Some(StatementKind::Directive { name, args }) })
+                if matches!(name.as_str(), ".if" | ".ifdef" | ".ifndef" | ".else" | ".endif") =>
+            {
+                (*line_num, labels.clone(), name.as_str(), args.as_slice())
+            }
+            // everything else is kept or dropped with its branch, errors in dropped lines included
+            Ok(statement) if active => {
+                for label in labels_defined(statement).into_iter().filter(|&label| defines.contains_key(label)) {
+                    let error = AsmblerError::DefineIsLabel(label.clone());
+                    out.push(Err(ParseError { error, line_num: statement.line_num, labels: Vec::new() }));
+                }
+                out.push(Ok(substitute(statement.clone(), &substitutes)));
+                continue;
+            }
+            Err(e) if active => {
+                out.push(Err(ParseError { error: e.error.clone(), line_num: e.line_num, labels: e.labels.clone() }));
+                continue;
+            }
+            _ => continue,
+        };
+        let error = |error| Err(ParseError { error, line_num, labels: Vec::new() });
+
+        let runtime = match name {
+            ".if" => args.iter().any(mentions_register),
+            ".else" | ".endif" => stack.last().is_some_and(|branch| branch.runtime),
+            _ => false,
+        };
+        if runtime {
+            if name == ".if" {
+                stack.push(Branch { parent_active: active, active, taken: true, seen_else: false, line_num, runtime: true });
+            } else if name == ".endif" {
+                stack.pop();
+            }
+            if active {
+                out.push(statement);
+            }
+            continue;
+        }
+
+        if active && !labels.is_empty() {
+            out.push(Ok(Statement { line_num, labels, kind: None }));
+        }
+
+        match name {
+            ".if" | ".ifdef" | ".ifndef" => {
+                // conditions inside a dropped branch are not looked at
+                let condition = if !active {
+                    Ok(false)
+                } else {
+                    match (name, args) {
+                        (".ifdef", [Term::Symbol(define)]) => Ok(defines.contains_key(define)),
+                        (".ifndef", [Term::Symbol(define)]) => Ok(!defines.contains_key(define)),
+                        (".if", [expr]) => evaluate(expr, defines).map(|value| value != 0),
+                        _ => Err(AsmblerError::SyntaxError),
+                    }
+                };
+                let condition = condition.unwrap_or_else(|e| {
+                    out.push(error(e));
+                    false
+                });
+                stack.push(Branch { parent_active: active, active: active && condition, taken: condition, seen_else: false, line_num, runtime: false });
+            }
+            ".else" => match stack.last_mut() {
+                Some(branch) if !branch.seen_else && args.is_empty() => {
+                    branch.active = branch.parent_active && !branch.taken;
+                    branch.seen_else = true;
+                }
+                Some(_) if !args.is_empty() => out.push(error(AsmblerError::SyntaxError)),
+                _ => out.push(error(AsmblerError::UnmatchedConditional)),
+            },
+            _ => match stack.pop() {
+                Some(_) if args.is_empty() => {}
+                Some(_) => out.push(error(AsmblerError::SyntaxError)),
+                None => out.push(error(AsmblerError::UnmatchedConditional)),
+            },
+        }
+    }
+
+    for branch in stack {
+        out.push(Err(ParseError { error: AsmblerError::UnterminatedConditional, line_num: branch.line_num, labels: Vec::new() }));
+    }
+
+    out
+}
+
+fn evaluate(expr: &Term, defines: &Defines) -> Result<i32, AsmblerError> {
+    match expr {
+        Term::Number(value) => Ok(*value),
+        Term::Symbol(name) => defines.get(name).copied().ok_or_else(|| AsmblerError::UndefinedName(name.clone())),
+        Term::Compare(left, op, right) => {
+            let (left, right) = (evaluate(left, defines)?, evaluate(right, defines)?);
+            let result = match *op {
+                "==" => left == right,
+                "!=" => left != right,
+                "<" => left < right,
+                "<=" => left <= right,
+                ">" => left > right,
+                ">=" => left >= right,
+                _ => return Err(AsmblerError::SyntaxError),
+            };
+            Ok(result as i32)
+        }
+        _ => Err(AsmblerError::InvalidOperand),
+    }
+}
+
+fn mentions_register(term: &Term) -> bool {
+    match term {
+        Term::Register(_) => true,
+        Term::Compare(left, _, right) => mentions_register(left) || mentions_register(right),
+        _ => false,
+    }
+}
+
+// label: in front of the line, proc name, .extern name and dw name value
+fn labels_defined(statement: &Statement) -> Vec<&String> {
+    let mut names: Vec<&String> = statement.labels.iter().collect();
+    match &statement.kind {
+        Some(StatementKind::Instruction { mnemonic, operands }) if mnemonic == "dw" => {
+            if let Some(Term::Symbol(name)) = operands.first() {
+                names.push(name);
+            }
+        }
+        Some(StatementKind::Instruction { mnemonic, operands }) if mnemonic == "proc" => {
+            names.extend(operands.iter().filter_map(|term| match term {
+                Term::Symbol(name) => Some(name),
+                _ => None,
+            }));
+        }
+        Some(StatementKind::Directive { name, args }) if name == ".extern" => {
+            names.extend(args.iter().filter_map(|term| match term {
+                Term::Symbol(name) => Some(name),
+                _ => None,
+            }));
+        }
+        _ => {}
+    }
+    names
+}
+
+// defined names in operands become their values
+fn substitute(statement: Statement, defines: &Defines) -> Statement {
+    if defines.is_empty() {
+        return statement;
+    }
+    let terms = |terms: Vec<Term>| terms.into_iter().map(|term| substitute_term(term, defines)).collect();
+    let kind = statement.kind.map(|kind| match kind {
+        StatementKind::Instruction { mnemonic, operands } => StatementKind::Instruction { mnemonic, operands: terms(operands) },
+        StatementKind::Directive { name, args } => StatementKind::Directive { name, args: terms(args) },
+    });
+    Statement { line_num: statement.line_num, labels: statement.labels, kind }
+}
+
+fn substitute_term(term: Term, defines: &Defines) -> Term {
+    let inner = |term: Box<Term>| Box::new(substitute_term(*term, defines));
+    match term {
+        Term::Symbol(name) => match defines.get(&name) {
+            Some(value) => Term::Number(*value),
+            None => Term::Symbol(name),
+        },
+        Term::Memory(term) => Term::Memory(inner(term)),
+        Term::Offset(base, offset) => Term::Offset(inner(base), inner(offset)),
+        Term::Range(first, last) => Term::Range(inner(first), inner(last)),
+        Term::Compare(left, op, right) => Term::Compare(inner(left), op, inner(right)),
+        // pages(name) and &name are always labels
+        term => term,
+    }
+}
+
+// -DNAME=value or -DNAME
+pub fn parse_define(flag: &str) -> Option<Result<(String, i32), AsmblerError>> {
+    let define = flag.strip_prefix("-D")?;
+    let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+    if !is_label_name(name) {
+        return Some(Err(AsmblerError::SyntaxError));
+    }
+    Some(parse_number(value.trim()).map(|value| (name.to_string(), value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asmbler::{Asmbler, AsmblerError};
+
+    fn assemble(source: &str, flags: &[&str]) -> (String, Vec<AsmblerError>) {
+        let result = Asmbler::assemble(source.to_string(), flags.iter().map(|flag| flag.to_string()).collect());
+        (result.contents, result.errors.into_iter().map(|(e, _, _)| e).collect())
+    }
+
+    #[test]
+    fn define_replaces_numbers() {
+        let (contents, errors) = assemble("mov acc N\n.org BASE\nhlt", &["-DN=7", "-DBASE=20"]);
+        assert_eq!(errors, vec![]);
+        assert_eq!(contents.lines().next(), Some("+210007"));
+        assert_eq!(contents.lines().nth(20), Some("+450000"));
+    }
+
+    #[test]
+    fn define_named_like_a_label_is_refused() {
+        let source = "loop:\n    jmp loop\nproc f\n    ret\nendp\n";
+        assert_eq!(assemble(source, &["-Dloop=5"]).1, vec![AsmblerError::DefineIsLabel("loop".to_string())]);
+        assert_eq!(assemble(source, &["-Df=5"]).1, vec![AsmblerError::DefineIsLabel("f".to_string())]);
+    }
+
+    #[test]
+    fn define_named_like_a_data_word_is_refused() {
+        let (_, errors) = assemble("mov acc [count]
+hlt
+.data
+dw count 7
+", &["-Dcount=3"]);
+        assert_eq!(errors, vec![AsmblerError::DefineIsLabel("count".to_string())]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{BTreeSet, HashSet};
+
+use isa::{Arg, Flow};
+
+use crate::encoder::decode;
+
+// runs of zero data words at least this long are skipped with .org
+const ZERO_RUN_FOR_ORG: usize = 4;
+
+// reads an .sml program the same way the simulator does
+pub fn parse_sml(contents: &str) -> Result<Vec<i32>, String> {
+    Ok(sml_lines(contents)?.into_iter().map(|(_, word)| word).collect())
+}
+
+// every word with the line it is on, from 0
+pub fn sml_lines(contents: &str) -> Result<Vec<(usize, i32)>, String> {
+    let mut words = Vec::new();
+    for (line_num, line) in contents.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() || line.eq_ignore_ascii_case("go") {
+            continue;
+        }
+        match line.parse::<i32>() {
+            Ok(word) => words.push((line_num, word)),
+            Err(_) => return Err(format!("Invalid word on line {}: {}", line_num + 1, line)),
+        }
+    }
+    Ok(words)
+}
+
+// turns an image back into asml, assembling the result gives the same words back
+pub fn disassemble(words: &[i32]) -> String {
+
+    let executed = reachable(words);
+
+    // every branch that is executed gets a label on its target
+    let mut targets = BTreeSet::new();
+    for &addr in &executed {
+        let (opcode, operand) = decode(words[addr]);
+        let Some(instr) = isa::by_opcode(opcode) else { continue };
+        let target = instr.target(addr as i32, operand);
+        if matches!(instr.flow, Flow::Jump | Flow::Branch) && target >= 0 && (target as usize) < words.len() {
+            targets.insert(target as usize);
+        }
+    }
+
+    // the assembler ends the code with a hlt of its own, so the last one in the image is left to it
+    // and everything after it goes in the data section that follows the code
+    let hlt = isa::encode(isa::opcode_of("HALT"), 0);
+    let implicit_hlt = words.iter().rposition(|&word| word == hlt);
+
+    let mut out = String::from("; disassembled by asmbler\n");
+    if implicit_hlt.is_none() {
+        out.push_str("; this program has no final hlt, assemble it with --no-implicit-hlt to get the same words back\n");
+    }
+
+    let mut addr = 0;
+    while addr < words.len() {
+        if Some(addr) == implicit_hlt {
+            out.push_str("\n; hlt added by the assembler\n.data\n");
+            addr += 1;
+            continue;
+        }
+
+        // skip long runs of zeros that are never executed
+        // not at 0 since code that starts later gets a jmp put there, and not at the end since nothing would follow the .org
+        let run = words[addr..].iter()
+            .enumerate()
+            .take_while(|&(offset, &word)| word == 0 && !executed.contains(&(addr + offset)) && !targets.contains(&(addr + offset)))
+            .count();
+        if addr != 0 && run >= ZERO_RUN_FOR_ORG && addr + run < words.len() {
+            out.push_str(&format!(".org {}\n", addr + run));
+            addr += run;
+            continue;
+        }
+
+        if targets.contains(&addr) {
+            out.push_str(&format!("L{}:\n", addr));
+        }
+
+        let word = words[addr];
+        let text = if executed.contains(&addr) {
+            instruction_text(word, addr, words.len())
+        } else {
+            None
+        };
+        match text {
+            Some(text) => out.push_str(&format!("{}\n", text)),
+            None => out.push_str(&format!("dw {}\n", word)),
+        }
+
+        addr += 1;
+    }
+
+    out
+}
+
+// addresses that can be executed starting from 0
+fn reachable(words: &[i32]) -> HashSet<usize> {
+    let mut executed = HashSet::new();
+    let mut pending = vec![0];
+
+    while let Some(addr) = pending.pop() {
+        if addr >= words.len() || !executed.insert(addr) {
+            continue;
+        }
+        // not something the assembler could have written, treat it as data
+        if instruction_text(words[addr], addr, words.len()).is_none() {
+            executed.remove(&addr);
+            continue;
+        }
+        let (opcode, operand) = decode(words[addr]);
+        let Some(instr) = isa::by_opcode(opcode) else { continue };
+        // instruction_text only accepts branches that land inside the image
+        let target = instr.target(addr as i32, operand) as usize;
+        match instr.flow {
+            Flow::Jump => pending.push(target),
+            Flow::Branch => {
+                pending.push(target);
+                pending.push(addr + 1);
+            }
+            // where an indirect jump goes is not in the word, code only reached that way comes out as dw
+            Flow::Stop | Flow::Indirect => {}
+            _ => pending.push(addr + 1),
+        }
+    }
+
+    executed
+}
+
+// the asml that assembles to exactly this word, None when there is none
+pub fn instruction_text(word: i32, addr: usize, image_len: usize) -> Option<String> {
+    let (opcode, operand) = decode(word);
+    let instr = isa::by_opcode(opcode)?;
+    let form = instr.form_for(operand)?;
+
+    let mut text = instr.mnemonic.to_string();
+    for arg in form {
+        let arg_text = match arg {
+            Arg::Mem => format!("[{}]", operand),
+            Arg::Indirect => format!("[[{}]]", operand),
+            Arg::IdxOffset if operand < 0 => format!("[idx{}]", operand),
+            Arg::IdxOffset => format!("[idx+{}]", operand),
+            Arg::Imm => operand.to_string(),
+            // a branch outside the image has nowhere to put its label
+            Arg::Target if operand as usize >= image_len => return None,
+            Arg::Target => format!("L{}", operand),
+            Arg::RelTarget => match addr as i32 + operand {
+                target if target < 0 || target as usize >= image_len => return None,
+                target => format!("L{}", target),
+            },
+            // HALT dumps nothing for a backwards range, the assembler does not accept one
+            Arg::Pages if operand / 100 > operand % 100 => return None,
+            Arg::Pages if operand / 100 == operand % 100 => (operand / 100).to_string(),
+            Arg::Pages => format!("{}..{}", operand / 100, operand % 100),
+            _ => arg.syntax().to_string(),
+        };
+        text.push(' ');
+        text.push_str(&arg_text);
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asmbler::Asmbler;
+
+    #[test]
+    fn sim_programs_round_trip() {
+        for name in ["arraysumV2", "gcd", "mm", "test"] {
+            let file = format!("../sim/{}.sml", name);
+            let contents = std::fs::read_to_string(&file).unwrap_or_else(|e| panic!("{}: {}", file, e));
+            let words = parse_sml(&contents).unwrap_or_else(|e| panic!("{}: {}", file, e));
+
+            let asml = disassemble(&words);
+            // the final hlt is only left out of the disassembly when the assembler will put it back
+            let flags = if asml.contains("; hlt added by the assembler") { vec![] } else { vec!["--no-implicit-hlt".to_string()] };
+            let result = Asmbler::assemble(asml.clone(), flags);
+            assert!(!result.has_errors(), "{} does not reassemble: {:?}\n{}", file, result.errors, asml);
+            assert_eq!(parse_sml(&result.contents), Ok(words), "{} comes back different from\n{}", file, asml);
+        }
+    }
+}
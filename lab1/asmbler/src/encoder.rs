@@ -0,0 +1,95 @@
+use isa::{MAX_OPCODE, MAX_OPERAND, MAX_WORD, MIN_OPERAND, MIN_WORD};
+
+use crate::asmbler::AsmblerError;
+use crate::extensions::Extensions;
+
+pub use isa::{decode, format_word};
+
+// builds an instruction word, rejecting anything that would not survive the simulator's decode
+pub fn encode_instruction(opcode: u32, operand: i32) -> Result<i32, AsmblerError> {
+
+    if opcode > MAX_OPCODE {
+        return Err(AsmblerError::OpcodeOutOfRange { opcode, max: MAX_OPCODE });
+    }
+
+    if !(MIN_OPERAND..=MAX_OPERAND).contains(&operand) {
+        return Err(AsmblerError::OperandOutOfRange { value: operand, min: MIN_OPERAND, max: MAX_OPERAND });
+    }
+
+    let word = isa::encode(opcode, operand);
+
+    debug_assert_eq!(decode(word), (opcode, operand), "encoded word does not round trip");
+
+    encode_data(word)
+}
+
+// a raw data word, as placed by dw
+pub fn encode_data(value: i32) -> Result<i32, AsmblerError> {
+
+    if !(MIN_WORD..=MAX_WORD).contains(&value) {
+        return Err(AsmblerError::WordOutOfRange { value, min: MIN_WORD, max: MAX_WORD });
+    }
+
+    Ok(value)
+}
+
+// the simulator's name for an opcode, or the mnemonic of one defined with .opcode or an ISA profile
+pub fn opcode_name(opcode: u32, extensions: &Extensions) -> Option<String> {
+    isa::by_opcode(opcode).map(|instr| instr.name.to_string())
+        .or_else(|| extensions.by_opcode(opcode).map(|ext| ext.mnemonic.to_ascii_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asmbler::Asmbler;
+
+    const OPERANDS: [i32; 5] = [MIN_OPERAND, -1, 0, 1, MAX_OPERAND];
+
+    #[test]
+    fn every_instruction_round_trips() {
+        for instr in isa::INSTRUCTIONS {
+            for operand in OPERANDS {
+                let word = encode_instruction(instr.opcode, operand)
+                    .unwrap_or_else(|e| panic!("{} {} did not encode: {:?}", instr.name, operand, e));
+                assert_eq!(decode(word), (instr.opcode, operand), "{} {} is {}", instr.name, operand, format_word(word));
+            }
+        }
+    }
+
+    #[test]
+    fn opcode_out_of_range() {
+        assert_eq!(encode_instruction(MAX_OPCODE + 1, 0), Err(AsmblerError::OpcodeOutOfRange { opcode: MAX_OPCODE + 1, max: MAX_OPCODE }));
+    }
+
+    #[test]
+    fn operand_out_of_range() {
+        for value in [MIN_OPERAND - 1, MAX_OPERAND + 1] {
+            assert_eq!(
+                encode_instruction(isa::opcode_of("LOADIM"), value),
+                Err(AsmblerError::OperandOutOfRange { value, min: MIN_OPERAND, max: MAX_OPERAND })
+            );
+        }
+    }
+
+    #[test]
+    fn word_out_of_range() {
+        for value in [MIN_WORD, MAX_WORD] {
+            assert_eq!(encode_data(value), Ok(value));
+        }
+        for value in [MIN_WORD - 1, MAX_WORD + 1] {
+            assert_eq!(encode_data(value), Err(AsmblerError::WordOutOfRange { value, min: MIN_WORD, max: MAX_WORD }));
+        }
+    }
+
+    // the same errors come out of the assembler against the line
+    #[test]
+    fn assembler_reports_ranges() {
+        let errors = |line: &str| -> Vec<AsmblerError> {
+            Asmbler::assemble(line.to_string(), Vec::new()).errors.into_iter().map(|(e, _, _)| e).collect()
+        };
+        assert_eq!(errors("mov acc 10000"), vec![AsmblerError::OperandOutOfRange { value: 10000, min: MIN_OPERAND, max: MAX_OPERAND }]);
+        assert_eq!(errors(".data\ndw 1000000"), vec![AsmblerError::WordOutOfRange { value: 1000000, min: MIN_WORD, max: MAX_WORD }]);
+        assert_eq!(errors(".opcode big 100 imm"), vec![AsmblerError::OpcodeOutOfRange { opcode: 100, max: MAX_OPCODE }]);
+    }
+}
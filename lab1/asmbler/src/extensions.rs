@@ -0,0 +1,87 @@
+use isa::{Arg, MAX_OPCODE};
+
+use crate::asmbler::AsmblerError;
+use crate::parser::{parse, StatementKind, Term};
+
+// an instruction the host adds with add_extension_instruction, unknown to the ISA table
+pub struct Extension {
+    pub mnemonic: String,
+    pub opcode: u32,
+    pub form: Vec<Arg>,
+}
+
+// mnemonics defined with .opcode or an ISA profile, on top of the ISA
+#[derive(Default)]
+pub struct Extensions {
+    defined: Vec<Extension>,
+}
+
+impl Extensions {
+    // .opcode name opcode shape - shape is none, or the pieces in order: acc idx [idx] mem imm label
+    pub fn define(&mut self, args: &[Term]) -> Result<(), AsmblerError> {
+        let (mnemonic, opcode, shape) = match args {
+            [Term::Symbol(mnemonic), Term::Number(opcode), shape @ ..] if !shape.is_empty() => {
+                (mnemonic.to_ascii_lowercase(), *opcode, shape)
+            }
+            _ => return Err(AsmblerError::SyntaxError),
+        };
+
+        if opcode <= 0 || opcode as u32 > MAX_OPCODE {
+            return Err(AsmblerError::OpcodeOutOfRange { opcode: opcode.unsigned_abs(), max: MAX_OPCODE });
+        }
+        let opcode = opcode as u32;
+        if isa::by_opcode(opcode).is_some() || self.defined.iter().any(|ext| ext.opcode == opcode) {
+            return Err(AsmblerError::OpcodeAlreadyDefined(opcode));
+        }
+        if isa::by_mnemonic(&mnemonic).next().is_some() || self.by_mnemonic(&mnemonic).next().is_some() {
+            return Err(AsmblerError::MnemonicAlreadyDefined(mnemonic));
+        }
+
+        let form = match shape {
+            [Term::Symbol(none)] if none.eq_ignore_ascii_case("none") => Vec::new(),
+            pieces => pieces.iter().map(shape_arg).collect::<Result<Vec<Arg>, AsmblerError>>()?,
+        };
+        // the operand is one number, so only one piece can carry it
+        if form.iter().filter(|arg| arg.is_operand()).count() > 1 {
+            return Err(AsmblerError::InvalidOperand);
+        }
+
+        self.defined.push(Extension { mnemonic, opcode, form });
+        Ok(())
+    }
+
+    // --opcode=beep 13 none, the same as the .opcode directive
+    pub fn define_from_flag(&mut self, spec: &str) -> Result<(), AsmblerError> {
+        match parse(&format!(".opcode {}", spec)).pop() {
+            Some(Ok(statement)) => match statement.kind {
+                Some(StatementKind::Directive { args, .. }) => self.define(&args),
+                _ => Err(AsmblerError::SyntaxError),
+            },
+            Some(Err(e)) => Err(e.error),
+            None => Err(AsmblerError::SyntaxError),
+        }
+    }
+
+    pub fn by_opcode(&self, opcode: u32) -> Option<&Extension> {
+        self.defined.iter().find(|ext| ext.opcode == opcode)
+    }
+
+    pub fn by_mnemonic<'a>(&'a self, mnemonic: &'a str) -> impl Iterator<Item = &'a Extension> + 'a {
+        self.defined.iter().filter(move |ext| ext.mnemonic == mnemonic)
+    }
+}
+
+fn shape_arg(term: &Term) -> Result<Arg, AsmblerError> {
+    match term {
+        Term::Register(reg) if reg == "acc" => Ok(Arg::Acc),
+        Term::Register(reg) if reg == "idx" => Ok(Arg::Idx),
+        Term::Memory(inner) if matches!(inner.as_ref(), Term::Register(reg) if reg == "idx") => Ok(Arg::AtIdx),
+        Term::Symbol(piece) => match piece.to_ascii_lowercase().as_str() {
+            "mem" => Ok(Arg::Mem),
+            "imm" => Ok(Arg::Imm),
+            "label" => Ok(Arg::Target),
+            _ => Err(AsmblerError::InvalidOperand),
+        },
+        _ => Err(AsmblerError::InvalidOperand),
+    }
+}
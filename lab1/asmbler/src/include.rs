@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use crate::asmbler::AsmblerError;
+use crate::lexer::{tokenize, Token};
+
+// where each line of the assembled text came from once .include "file" lines are filled in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sources {
+    // the input file first, then every included file
+    pub files: Vec<String>,
+    // (file index, line in that file) for every line of the text
+    lines: Vec<(usize, usize)>,
+}
+
+impl Sources {
+    // (file name, line in it) for a line of the text
+    pub fn location(&self, line_num: usize) -> (&str, usize) {
+        let (file, line) = self.lines.get(line_num).copied().unwrap_or((0, line_num));
+        (&self.files[file], line)
+    }
+
+    // a line of a file as a line of its own, for line info that does not come from one text
+    pub fn add(&mut self, file: &str, line: usize) -> usize {
+        let file = match self.files.iter().position(|name| name == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            }
+        };
+        self.lines.push((file, line));
+        self.lines.len() - 1
+    }
+}
+
+// the text with every .include "file" filled in, the .include line stays and the file's lines
+// follow it, paths are relative to the file the .include is in
+pub fn expand(name: &str, contents: &str, dir: &Path) -> (String, Sources, Vec<(AsmblerError, usize)>) {
+    let mut expansion = Expansion {
+        text: Vec::new(),
+        sources: Sources { files: vec![name.to_string()], lines: Vec::new() },
+        errors: Vec::new(),
+        stack: vec![dir.join(name)],
+        root: dir.to_path_buf(),
+    };
+    expansion.file(0, contents, dir);
+    (expansion.text.join("\n"), expansion.sources, expansion.errors)
+}
+
+struct Expansion {
+    text: Vec<String>,
+    sources: Sources,
+    errors: Vec<(AsmblerError, usize)>,
+    // the files being included, to catch a file that includes itself
+    stack: Vec<PathBuf>,
+    // included files are named from the input file's directory
+    root: PathBuf,
+}
+
+impl Expansion {
+    fn file(&mut self, file: usize, contents: &str, dir: &Path) {
+        for (line_num, line) in contents.split('\n').enumerate() {
+            self.text.push(line.to_string());
+            self.sources.lines.push((file, line_num));
+            let text_line = self.text.len() - 1;
+
+            let Some(path) = include_path(line) else { continue };
+            let path = dir.join(path);
+
+            if self.stack.iter().any(|open| same_file(open, &path)) {
+                self.errors.push((AsmblerError::IncludeCycle(path.display().to_string()), text_line));
+                continue;
+            }
+            let included = match std::fs::read_to_string(&path) {
+                Ok(included) => included,
+                Err(_) => {
+                    self.errors.push((AsmblerError::IncludeNotFound(path.display().to_string()), text_line));
+                    continue;
+                }
+            };
+
+            self.sources.files.push(path.strip_prefix(&self.root).unwrap_or(&path).display().to_string());
+            let index = self.sources.files.len() - 1;
+            self.stack.push(path.clone());
+            self.file(index, &included, path.parent().unwrap_or(dir));
+            self.stack.pop();
+        }
+    }
+}
+
+// the file named by an .include "file" line
+fn include_path(line: &str) -> Option<String> {
+    match tokenize(line).ok()?.as_slice() {
+        [Token::Ident(name), Token::Str(path)] if name.eq_ignore_ascii_case(".include") => Some(path.clone()),
+        _ => None,
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_cycle() {
+        let dir = std::env::temp_dir().join(format!("asmbler-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.asml"), ".include \"b.asml\"\n").unwrap();
+        std::fs::write(dir.join("b.asml"), "    hlt\n.include \"a.asml\"\n").unwrap();
+
+        let (_, sources, errors) = expand("a.asml", ".include \"b.asml\"\n", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the .include in b.asml is line 2 of the text
+        assert_eq!(errors, vec![(AsmblerError::IncludeCycle(dir.join("a.asml").display().to_string()), 2)]);
+        assert_eq!(sources.location(2), ("b.asml", 1));
+    }
+}
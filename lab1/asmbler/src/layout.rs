@@ -0,0 +1,597 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::asmbler::AsmblerError;
+use crate::encoder::{encode_data, encode_instruction, format_word};
+use crate::include::Sources;
+use crate::object::{Field, LineInfo, Object, Reloc, Symbol, Target};
+
+pub const PAGE_SIZE: u32 = isa::WORDS_PER_PAGE as u32;
+pub const MEMORY_SIZE: u32 = isa::MEMORY_SIZE as u32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    Code,
+    Data,
+}
+
+impl Section {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Section::Code => "code",
+            Section::Data => "data",
+        }
+    }
+}
+
+// where a block starts once the whole program is known
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    // straight after the previous block of the same section
+    Follow,
+    // .org addr
+    Fixed(u32),
+    // .align n, after the previous block rounded up to a multiple of n
+    Align(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Lit(i32),
+    Label(String),
+    // hlt first..last
+    Pages(PageBound, PageBound),
+    // jmpr label, the distance from the instruction to the label
+    Relative(String),
+}
+
+// one end of a hlt page range
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageBound {
+    Page(u32),
+    // pages(label), the page the label is on
+    PageOf(String),
+}
+
+impl Operand {
+    // addr is where the word itself ends up
+    fn resolve(&self, labels: &BTreeMap<String, u32>, addr: u32) -> Result<i32, AsmblerError> {
+        let address_of = |name: &String| labels.get(name).copied().ok_or_else(|| AsmblerError::UndefinedLabel(name.clone()));
+        let page_of = |bound: &PageBound| match bound {
+            PageBound::Page(page) => Ok(*page),
+            PageBound::PageOf(name) => address_of(name).map(|addr| addr / PAGE_SIZE),
+        };
+        match self {
+            Operand::Lit(value) => Ok(*value),
+            Operand::Label(name) => address_of(name).map(|addr| addr as i32),
+            Operand::Relative(name) => address_of(name).map(|target| target as i32 - addr as i32),
+            Operand::Pages(first, last) => {
+                let (first, last) = (page_of(first)?, page_of(last)?);
+                if first > last {
+                    return Err(AsmblerError::InvalidOperand);
+                }
+                // two decimal digits each, the way HALT unpacks them
+                Ok((first * 100 + last) as i32)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    Instruction { opcode: u32, operand: Operand },
+    // a literal or the address of a label
+    Data(Operand),
+}
+
+struct Block {
+    section: Section,
+    origin: Origin,
+    // (slot, line_num), words the assembler adds on its own have no line
+    slots: Vec<(Slot, Option<usize>)>,
+}
+
+// (block_index, offset, line_num)
+type LabelPos = (usize, usize, usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl MemoryRegion {
+    pub fn pages(&self) -> (u32, u32) {
+        (self.start / PAGE_SIZE, self.end / PAGE_SIZE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlacedWord {
+    pub word: i32,
+    pub line_num: Option<usize>,
+    pub is_data: bool,
+}
+
+// the program after layout, every word at its final address
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub words: BTreeMap<u32, PlacedWord>,
+    pub regions: Vec<MemoryRegion>,
+    pub labels: BTreeMap<String, u32>,
+}
+
+impl Image {
+    // one word per line from address 0, the gaps filled with 0
+    pub fn to_sml(&self) -> String {
+        let end = self.words.keys().next_back().map_or(0, |&addr| addr + 1);
+        (0..end)
+            .map(|addr| format_word(self.words.get(&addr).map_or(0, |placed| placed.word)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+// one row per section run with the pages it touches, the same pages hlt can dump
+pub fn memory_map_report(regions: &[MemoryRegion]) -> String {
+    let mut report = String::from("section  start  end   pages\n");
+    for region in regions {
+        let (first_page, last_page) = region.pages();
+        let pages = if first_page == last_page {
+            format!("{:02}", first_page)
+        } else {
+            format!("{:02}-{:02}", first_page, last_page)
+        };
+        report.push_str(&format!("{:<8} {:04}   {:04}  {}\n", region.name, region.start, region.end, pages));
+    }
+    report
+}
+
+pub struct Program {
+    lines: Vec<String>,
+    blocks: Vec<Block>,
+    // the block each section is currently appending to
+    current: HashMap<&'static str, usize>,
+    section: Section,
+    labels: HashMap<String, LabelPos>,
+    // dw [addr] value, (value, line_num)
+    absolute: BTreeMap<u32, (i32, usize)>,
+    // (label, line_num)
+    entry: Option<(String, usize)>,
+    // .global and .extern, (label, line_num)
+    globals: Vec<(String, usize)>,
+    externs: Vec<(String, usize)>,
+    pub errors: Vec<(AsmblerError, String, usize)>,
+}
+
+impl Program {
+    pub fn new(contents: &str) -> Self {
+        Program {
+            lines: contents.split('\n').map(|line| line.trim().to_string()).collect(),
+            blocks: Vec::new(),
+            current: HashMap::new(),
+            section: Section::Code,
+            labels: HashMap::new(),
+            absolute: BTreeMap::new(),
+            entry: None,
+            globals: Vec::new(),
+            externs: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn error(&mut self, error: AsmblerError, line_num: usize) {
+        let line = self.lines.get(line_num).cloned().unwrap_or_default();
+        self.errors.push((error, line, line_num));
+    }
+
+    pub fn set_section(&mut self, section: Section) {
+        self.section = section;
+    }
+
+    // .org addr - everything after this in the current section is placed from addr
+    pub fn org(&mut self, addr: u32) {
+        self.new_block(Origin::Fixed(addr));
+    }
+
+    // .align n - move the current section up to the next multiple of n
+    pub fn align(&mut self, n: u32) {
+        self.new_block(Origin::Align(n));
+    }
+
+    pub fn set_entry(&mut self, label: &str, line_num: usize) {
+        if self.entry.is_some() {
+            self.error(AsmblerError::EntryAlreadyDefined, line_num);
+            return;
+        }
+        self.entry = Some((label.to_string(), line_num));
+    }
+
+    // .global label - other modules can use the label when this one is an object file
+    pub fn export(&mut self, label: &str, line_num: usize) {
+        if !self.globals.iter().any(|(name, _)| name == label) {
+            self.globals.push((label.to_string(), line_num));
+        }
+    }
+
+    // .extern label - a label another module defines, filled in by the linker
+    pub fn import(&mut self, label: &str, line_num: usize) {
+        if !self.externs.iter().any(|(name, _)| name == label) {
+            self.externs.push((label.to_string(), line_num));
+        }
+    }
+
+    pub fn define_label(&mut self, name: &str, line_num: usize) {
+        if self.labels.contains_key(name) {
+            self.error(AsmblerError::LabelAlreadyDefined, line_num);
+            return;
+        }
+        let block = self.current_block();
+        let offset = self.blocks[block].slots.len();
+        self.labels.insert(name.to_string(), (block, offset, line_num));
+    }
+
+    pub fn emit(&mut self, opcode: u32, operand: Operand, line_num: usize) {
+        // literal operands are checked now so the error points at the right line
+        if let Operand::Lit(value) = operand
+            && let Err(e) = encode_instruction(opcode, value)
+        {
+            self.error(e, line_num);
+        }
+        self.push(Slot::Instruction { opcode, operand }, Some(line_num));
+    }
+
+    // dw value - a data word at the current location
+    pub fn emit_data(&mut self, value: i32, line_num: usize) {
+        if let Err(e) = encode_data(value) {
+            self.error(e, line_num);
+        }
+        self.push(Slot::Data(Operand::Lit(value)), Some(line_num));
+    }
+
+    // dw &label - a data word holding the address of a label
+    pub fn emit_address(&mut self, label: &str, line_num: usize) {
+        self.push(Slot::Data(Operand::Label(label.to_string())), Some(line_num));
+    }
+
+    // dw [addr] value - a data word at a fixed address
+    pub fn define_word_at(&mut self, addr: u32, value: i32, line_num: usize) {
+        if addr >= MEMORY_SIZE {
+            self.error(AsmblerError::MemoryRequestOutOfBounds, line_num);
+            return;
+        }
+        if let Err(e) = encode_data(value) {
+            self.error(e, line_num);
+            return;
+        }
+        if self.absolute.contains_key(&addr) {
+            self.error(AsmblerError::AddressConflict(addr), line_num);
+            return;
+        }
+        self.absolute.insert(addr, (value, line_num));
+    }
+
+    fn push(&mut self, slot: Slot, line_num: Option<usize>) {
+        let block = self.current_block();
+        self.blocks[block].slots.push((slot, line_num));
+    }
+
+    fn current_block(&mut self) -> usize {
+        match self.current.get(self.section.name()) {
+            Some(&block) => block,
+            None => self.new_block(Origin::Follow),
+        }
+    }
+
+    fn new_block(&mut self, origin: Origin) -> usize {
+        self.blocks.push(Block { section: self.section, origin, slots: Vec::new() });
+        let block = self.blocks.len() - 1;
+        self.current.insert(self.section.name(), block);
+        block
+    }
+
+    // places every block, resolves labels and encodes the final words
+    pub fn link(mut self, implicit_hlt: bool) -> Result<Image, Vec<(AsmblerError, String, usize)>> {
+
+        // errors about words the assembler added itself are put on the last line
+        let last_line = self.lines.len().saturating_sub(1);
+
+        if implicit_hlt {
+            self.set_section(Section::Code);
+            self.push(Slot::Instruction { opcode: isa::opcode_of("HALT"), operand: Operand::Lit(0) }, None);
+        }
+
+        // an .entry anywhere but the first word of code needs a jmp at 0, the code moves up to make
+        // room for it unless something was put at 0 with .org or dw [0], which is then a conflict
+        let first_code = self.blocks.iter().position(|block| block.section == Section::Code && !block.slots.is_empty());
+        let entry_is_first_word = self.entry.as_ref()
+            .and_then(|(label, _)| self.labels.get(label))
+            .is_some_and(|&(block, offset, _)| Some(block) == first_code && offset == 0);
+        let zero_is_taken = self.absolute.contains_key(&0)
+            || self.blocks.iter().any(|block| block.origin == Origin::Fixed(0) && !block.slots.is_empty());
+        let reserve_entry = self.entry.is_some() && !entry_is_first_word && !zero_is_taken;
+
+        // code is placed from 0, or 1 after the entry jmp, data follows the end of the code
+        let mut bases = vec![0; self.blocks.len()];
+        let mut cursor = if reserve_entry { 1 } else { 0 };
+        for section in [Section::Code, Section::Data] {
+            for (index, block) in self.blocks.iter().enumerate() {
+                if block.section != section {
+                    continue;
+                }
+                let base = match block.origin {
+                    Origin::Follow => cursor,
+                    Origin::Fixed(addr) => addr,
+                    Origin::Align(n) => cursor.div_ceil(n) * n,
+                };
+                bases[index] = base;
+                cursor = base + block.slots.len() as u32;
+            }
+        }
+
+        let mut labels = BTreeMap::new();
+        for (name, &(block, offset, _)) in &self.labels {
+            labels.insert(name.clone(), bases[block] + offset as u32);
+        }
+
+        let mut words: BTreeMap<u32, PlacedWord> = BTreeMap::new();
+        let mut regions = Vec::new();
+        let mut errors = Vec::new();
+
+        let mut place = |addr: u32, placed: PlacedWord, errors: &mut Vec<(AsmblerError, usize)>| {
+            let line_num = placed.line_num.unwrap_or(last_line);
+            if addr >= MEMORY_SIZE {
+                errors.push((AsmblerError::MemoryRequestOutOfBounds, line_num));
+            } else if let Entry::Vacant(slot) = words.entry(addr) {
+                slot.insert(placed);
+            } else {
+                errors.push((AsmblerError::AddressConflict(addr), line_num));
+            }
+        };
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let base = bases[index];
+            for (offset, &(ref slot, line_num)) in block.slots.iter().enumerate() {
+                let addr = base + offset as u32;
+                let error_line = line_num.unwrap_or(last_line);
+                let word = match slot {
+                    Slot::Instruction { opcode, operand } => {
+                        let value = match operand.resolve(&labels, addr) {
+                            Ok(value) => value,
+                            Err(e) => {
+                                errors.push((e, error_line));
+                                continue;
+                            }
+                        };
+                        match encode_instruction(*opcode, value) {
+                            Ok(word) => word,
+                            // literal operands were already reported by emit
+                            Err(e) => {
+                                if !matches!(operand, Operand::Lit(_)) {
+                                    errors.push((e, error_line));
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    // literals were checked by emit_data and an address always fits in a word
+                    Slot::Data(value) => match value.resolve(&labels, addr) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            errors.push((e, error_line));
+                            continue;
+                        }
+                    },
+                };
+                let is_data = matches!(slot, Slot::Data(_));
+                place(addr, PlacedWord { word, line_num, is_data }, &mut errors);
+            }
+            if !block.slots.is_empty() {
+                regions.push(MemoryRegion {
+                    name: block.section.name().to_string(),
+                    start: base,
+                    end: base + block.slots.len() as u32 - 1,
+                });
+            }
+        }
+
+        // dw [addr] value, grouped into runs of neighbouring addresses for the map
+        let mut run: Option<MemoryRegion> = None;
+        for (&addr, &(value, line_num)) in &self.absolute {
+            place(addr, PlacedWord { word: value, line_num: Some(line_num), is_data: true }, &mut errors);
+            match run.as_mut() {
+                Some(region) if region.end + 1 == addr => region.end = addr,
+                _ => {
+                    if let Some(region) = run.take() {
+                        regions.push(region);
+                    }
+                    run = Some(MemoryRegion { name: "dw".to_string(), start: addr, end: addr });
+                }
+            }
+        }
+        if let Some(region) = run {
+            regions.push(region);
+        }
+
+        // the simulator always starts at address 0, the jmp is placed last so a word put at 0
+        // by the program is reported as a conflict with the .entry line
+        let code_start = first_code.map(|index| bases[index]);
+        let entry = match &self.entry {
+            Some((label, line_num)) => match labels.get(label) {
+                Some(&addr) => Some((addr, Some(*line_num))),
+                None => {
+                    errors.push((AsmblerError::UndefinedLabel(label.clone()), *line_num));
+                    None
+                }
+            },
+            None => code_start.map(|addr| (addr, None)),
+        };
+        if let Some((addr, line_num)) = entry
+            && addr != 0
+        {
+            match encode_instruction(isa::opcode_of("BRANCH"), addr as i32) {
+                Ok(word) => place(0, PlacedWord { word, line_num, is_data: false }, &mut errors),
+                Err(e) => errors.push((e, line_num.unwrap_or(last_line))),
+            }
+            regions.push(MemoryRegion { name: "entry".to_string(), start: 0, end: 0 });
+        }
+
+        for (error, line_num) in errors {
+            self.error(error, line_num);
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        regions.sort_by_key(|region| region.start);
+        Ok(Image { words, regions, labels })
+    }
+
+    // the module as an object file, each section placed from 0 and every use of a label
+    // recorded so the linker can move it, .org and dw [addr] are refused before this
+    pub fn link_object(mut self, sources: &Sources) -> Result<Object, Vec<(AsmblerError, String, usize)>> {
+
+        let last_line = self.lines.len().saturating_sub(1);
+
+        let mut object = Object::default();
+        let mut bases = vec![0; self.blocks.len()];
+        for section in [Section::Code, Section::Data] {
+            let mut cursor: u32 = 0;
+            let mut align = 1;
+            for (index, block) in self.blocks.iter().enumerate() {
+                if block.section != section {
+                    continue;
+                }
+                let base = match block.origin {
+                    Origin::Align(n) => {
+                        // the linker keeps the section on a multiple of every .align in it
+                        align = align / gcd(align, n) * n;
+                        cursor.div_ceil(n) * n
+                    }
+                    Origin::Follow | Origin::Fixed(_) => cursor,
+                };
+                bases[index] = base;
+                cursor = base + block.slots.len() as u32;
+            }
+            let segment = object.segment_mut(section);
+            segment.words = vec![0; cursor as usize];
+            segment.align = align;
+        }
+
+        let mut errors = Vec::new();
+
+        // (section, offset) of every label defined here
+        let mut placed = BTreeMap::new();
+        for (name, &(block, offset, _)) in &self.labels {
+            placed.insert(name.clone(), (self.blocks[block].section, bases[block] + offset as u32));
+        }
+        for (name, line_num) in &self.externs {
+            if placed.contains_key(name) {
+                errors.push((AsmblerError::LabelAlreadyDefined, *line_num));
+            }
+        }
+        let target = |name: &String| match placed.get(name) {
+            Some(&(section, offset)) => Ok((Target::Section(section), offset as i32)),
+            None if self.externs.iter().any(|(extern_name, _)| extern_name == name) => Ok((Target::Symbol(name.clone()), 0)),
+            None => Err(AsmblerError::UndefinedLabel(name.clone())),
+        };
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            for (offset, &(ref slot, line_num)) in block.slots.iter().enumerate() {
+                let addr = bases[index] + offset as u32;
+                let error_line = line_num.unwrap_or(last_line);
+                let (field, operand) = match slot {
+                    Slot::Instruction { operand, .. } => (Field::Operand, operand),
+                    Slot::Data(operand) => (Field::Word, operand),
+                };
+                let value = match operand {
+                    Operand::Label(name) => match target(name) {
+                        Ok((target, value)) => {
+                            object.relocs.push(Reloc { section: block.section, offset: addr, field, target });
+                            value
+                        }
+                        Err(e) => {
+                            errors.push((e, error_line));
+                            continue;
+                        }
+                    },
+                    // a branch within the section does not change when the section moves,
+                    // to anywhere else the linker works out the distance
+                    Operand::Relative(name) => match target(name) {
+                        Ok((Target::Section(section), offset)) if section == block.section => offset - addr as i32,
+                        Ok((target, value)) => {
+                            object.relocs.push(Reloc { section: block.section, offset: addr, field: Field::Relative, target });
+                            value
+                        }
+                        Err(e) => {
+                            errors.push((e, error_line));
+                            continue;
+                        }
+                    },
+                    // the page of a label is only known once the program is linked
+                    Operand::Pages(PageBound::PageOf(_), _) | Operand::Pages(_, PageBound::PageOf(_)) => {
+                        errors.push((AsmblerError::NotRelocatable, error_line));
+                        continue;
+                    }
+                    operand => match operand.resolve(&BTreeMap::new(), addr) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            errors.push((e, error_line));
+                            continue;
+                        }
+                    },
+                };
+                let word = match slot {
+                    Slot::Instruction { opcode, operand } => match encode_instruction(*opcode, value) {
+                        Ok(word) => word,
+                        // literal operands were already reported by emit
+                        Err(e) => {
+                            if !matches!(operand, Operand::Lit(_)) {
+                                errors.push((e, error_line));
+                            }
+                            continue;
+                        }
+                    },
+                    Slot::Data(_) => value,
+                };
+                object.segment_mut(block.section).words[addr as usize] = word;
+                if let Some(line_num) = line_num {
+                    let (file, line) = sources.location(line_num);
+                    object.lines.push(LineInfo { section: block.section, offset: addr, line: line + 1, file: file.to_string() });
+                }
+            }
+        }
+
+        for (name, line_num) in &self.globals {
+            if !placed.contains_key(name) {
+                errors.push((AsmblerError::UndefinedLabel(name.clone()), *line_num));
+            }
+        }
+        for (name, &(section, offset)) in &placed {
+            let global = self.globals.iter().any(|(global, _)| global == name);
+            object.symbols.push(Symbol { name: name.clone(), section, offset, global });
+        }
+        object.externs = self.externs.iter().map(|(name, _)| name.clone()).collect();
+
+        if let Some((label, line_num)) = &self.entry {
+            match placed.get(label) {
+                Some(&position) => object.entry = Some(position),
+                None => errors.push((AsmblerError::UndefinedLabel(label.clone()), *line_num)),
+            }
+        }
+
+        for (error, line_num) in errors {
+            self.error(error, line_num);
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        Ok(object)
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
@@ -0,0 +1,283 @@
+use crate::asmbler::AsmblerError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // names, mnemonics and registers, directives keep their leading '.'
+    Ident(String),
+    // decimal, 0x hex, 0b binary or a 'c' character
+    Number(i32),
+    Str(String),
+    LBracket,
+    RBracket,
+    Colon,
+    Comma,
+    LParen,
+    RParen,
+    // .. between the ends of a range
+    DotDot,
+    // &label, the address of a label
+    Ampersand,
+    Plus,
+    // only a '-' that does not start a number
+    Minus,
+    // 1b or 1f, the nearest numeric label 1: before or after
+    NumericRef(u32, bool),
+    // == != < <= > >=
+    Compare(&'static str),
+}
+
+// splits one source line into tokens, everything after a ';' outside a literal is a comment
+// on a mistake the tokens before it are handed back too, so labels in front of it are not lost
+pub fn tokenize(line: &str) -> Result<Vec<Token>, (AsmblerError, Vec<Token>)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => pos += 1,
+            '[' => {
+                tokens.push(Token::LBracket);
+                pos += 1;
+            }
+            ']' => {
+                tokens.push(Token::RBracket);
+                pos += 1;
+            }
+            ':' => {
+                tokens.push(Token::Colon);
+                pos += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                pos += 1;
+            }
+            '+' => {
+                tokens.push(Token::Plus);
+                pos += 1;
+            }
+            '-' if !chars.get(pos + 1).is_some_and(|c| c.is_ascii_digit()) => {
+                tokens.push(Token::Minus);
+                pos += 1;
+            }
+            '&' => {
+                tokens.push(Token::Ampersand);
+                pos += 1;
+            }
+            '=' | '!' | '<' | '>' => {
+                let op = match (c, chars.get(pos + 1)) {
+                    ('=', Some('=')) => "==",
+                    ('!', Some('=')) => "!=",
+                    ('<', Some('=')) => "<=",
+                    ('>', Some('=')) => ">=",
+                    ('<', _) => "<",
+                    ('>', _) => ">",
+                    // .for idx = 0 to 9
+                    ('=', _) => "=",
+                    _ => return Err((AsmblerError::UnexpectedCharacter(c), tokens)),
+                };
+                tokens.push(Token::Compare(op));
+                pos += op.len();
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                pos += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                pos += 1;
+            }
+            '.' if chars.get(pos + 1) == Some(&'.') => {
+                tokens.push(Token::DotDot);
+                pos += 2;
+            }
+            '"' => {
+                let (text, next) = match read_quoted(&chars, pos, '"') {
+                    Ok(quoted) => quoted,
+                    Err(e) => return Err((e, tokens)),
+                };
+                tokens.push(Token::Str(text));
+                pos = next;
+            }
+            '\'' => {
+                let (text, next) = match read_quoted(&chars, pos, '\'') {
+                    Ok(quoted) => quoted,
+                    Err(e) => return Err((e, tokens)),
+                };
+                let mut text = text.chars();
+                match (text.next(), text.next()) {
+                    (Some(c), None) if c.is_ascii() => tokens.push(Token::Number(c as i32)),
+                    _ => return Err((AsmblerError::InvalidNumber(chars[pos..next].iter().collect()), tokens)),
+                }
+                pos = next;
+            }
+            c if c.is_ascii_digit() || (c == '-' && chars.get(pos + 1).is_some_and(|c| c.is_ascii_digit())) => {
+                let start = pos;
+                pos += 1;
+                while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                    pos += 1;
+                }
+                let text: String = chars[start..pos].iter().collect();
+                if let Some(reference) = numeric_ref(&text) {
+                    tokens.push(reference);
+                    continue;
+                }
+                match parse_number(&text) {
+                    Ok(value) => tokens.push(Token::Number(value)),
+                    Err(e) => return Err((e, tokens)),
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                let start = pos;
+                pos += 1;
+                while pos < chars.len()
+                    && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_' || (chars[pos] == '.' && chars.get(pos + 1) != Some(&'.')))
+                {
+                    pos += 1;
+                }
+                tokens.push(Token::Ident(chars[start..pos].iter().collect()));
+            }
+            c => return Err((AsmblerError::UnexpectedCharacter(c), tokens)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+// 1b is a reference back to the last 1:, 1f forward to the next, 0b on its own is not binary
+fn numeric_ref(text: &str) -> Option<Token> {
+    let (digits, forward) = match text.as_bytes().last()? {
+        b'b' => (&text[..text.len() - 1], false),
+        b'f' => (&text[..text.len() - 1], true),
+        _ => return None,
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok().map(|number| Token::NumericRef(number, forward))
+}
+
+// 42, -42, 0x2a, 0b101010
+pub fn parse_number(text: &str) -> Result<i32, AsmblerError> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let lower = digits.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2)
+    } else {
+        lower.parse::<i64>()
+    };
+
+    let value = match value {
+        Ok(value) if value <= i32::MAX as i64 => value as i32,
+        _ => return Err(AsmblerError::InvalidNumber(text.to_string())),
+    };
+
+    // the sign of a zero word can not be stored
+    if negative && value == 0 {
+        return Err(AsmblerError::NegativeZero);
+    }
+
+    Ok(if negative { -value } else { value })
+}
+
+// the text between a pair of quotes with \n \t \0 \\ \' \" escapes, and the position after the closing quote
+fn read_quoted(chars: &[char], start: usize, quote: char) -> Result<(String, usize), AsmblerError> {
+    let mut text = String::new();
+    let mut pos = start + 1;
+
+    while pos < chars.len() {
+        match chars[pos] {
+            c if c == quote => return Ok((text, pos + 1)),
+            '\\' => {
+                let escaped = match chars.get(pos + 1) {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some(&c @ ('\\' | '\'' | '"')) => c,
+                    _ => return Err(AsmblerError::InvalidEscape),
+                };
+                text.push(escaped);
+                pos += 2;
+            }
+            c => {
+                text.push(c);
+                pos += 1;
+            }
+        }
+    }
+
+    Err(AsmblerError::UnterminatedLiteral)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(name: &str) -> Token {
+        Token::Ident(name.to_string())
+    }
+
+    #[test]
+    fn comment_ends_the_line() {
+        assert_eq!(tokenize("mov acc 5 ; load 5"), Ok(vec![ident("mov"), ident("acc"), Token::Number(5)]));
+        assert_eq!(tokenize("; only a comment"), Ok(vec![]));
+        // not inside a literal
+        assert_eq!(tokenize(".ascii \"a;b\" ; c"), Ok(vec![ident(".ascii"), Token::Str("a;b".to_string())]));
+        assert_eq!(tokenize("dw ';'"), Ok(vec![ident("dw"), Token::Number(';' as i32)]));
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("-42"), Ok(-42));
+        assert_eq!(parse_number("0x2a"), Ok(42));
+        assert_eq!(parse_number("0X2A"), Ok(42));
+        assert_eq!(parse_number("-0x2a"), Ok(-42));
+        assert_eq!(parse_number("0b101010"), Ok(42));
+        assert_eq!(parse_number("0x"), Err(AsmblerError::InvalidNumber("0x".to_string())));
+        assert_eq!(parse_number("0b102"), Err(AsmblerError::InvalidNumber("0b102".to_string())));
+        assert_eq!(tokenize("'A' '\\n' '\\''"), Ok(vec![Token::Number(65), Token::Number(10), Token::Number(39)]));
+        assert_eq!(tokenize("'ab'").map_err(|(e, _)| e), Err(AsmblerError::InvalidNumber("'ab'".to_string())));
+        assert_eq!(tokenize("\"a\\tb\""), Ok(vec![Token::Str("a\tb".to_string())]));
+        assert_eq!(tokenize("\"open").map_err(|(e, _)| e), Err(AsmblerError::UnterminatedLiteral));
+    }
+
+    #[test]
+    fn negative_zero() {
+        assert_eq!(parse_number("-0"), Err(AsmblerError::NegativeZero));
+        assert_eq!(parse_number("-0x0"), Err(AsmblerError::NegativeZero));
+        assert_eq!(parse_number("0"), Ok(0));
+    }
+
+    #[test]
+    fn numeric_references() {
+        assert_eq!(tokenize("jmp 1b 2f"), Ok(vec![ident("jmp"), Token::NumericRef(1, false), Token::NumericRef(2, true)]));
+        // 0b on its own is not binary, 0b1 is
+        assert_eq!(tokenize("0b 0b1"), Ok(vec![Token::NumericRef(0, false), Token::Number(1)]));
+    }
+
+    #[test]
+    fn offsets_and_addresses() {
+        assert_eq!(
+            tokenize("[idx+table] [idx-3] &label"),
+            Ok(vec![
+                Token::LBracket, ident("idx"), Token::Plus, ident("table"), Token::RBracket,
+                Token::LBracket, ident("idx"), Token::Number(-3), Token::RBracket,
+                Token::Ampersand, ident("label"),
+            ])
+        );
+        assert_eq!(tokenize("idx - 3"), Ok(vec![ident("idx"), Token::Minus, Token::Number(3)]));
+    }
+
+    #[test]
+    fn mistakes_keep_the_tokens_before_them() {
+        assert_eq!(tokenize("start: mov acc $5"), Err((AsmblerError::UnexpectedCharacter('$'), vec![ident("start"), Token::Colon, ident("mov"), ident("acc")])));
+    }
+}
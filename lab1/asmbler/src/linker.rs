@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+
+use crate::asmbler::AsmblerError;
+use crate::encoder::{decode, encode_data, encode_instruction};
+use crate::include::Sources;
+use crate::layout::{Image, MemoryRegion, PlacedWord, Section, MEMORY_SIZE};
+use crate::object::{Field, Object, Target};
+
+// the version of the image written with --relocatable, the simulator checks it
+pub const IMAGE_VERSION: u32 = 1;
+
+pub struct Linked {
+    // placed from 0
+    pub image: Image,
+    // every word that holds an address in the image, so a loader can move it
+    pub relocs: Vec<(u32, Field)>,
+    // the source lines of the words, image.words line numbers index into it
+    pub sources: Sources,
+}
+
+impl Linked {
+    // an image the simulator can load at any address with --base
+    //
+    // simpletron image <version>
+    // words <count>          followed by one word per line, running starts at the first
+    // reloc <offset> operand|word
+    pub fn to_image_text(&self) -> String {
+        let sml = self.image.to_sml();
+        let mut out = format!("simpletron image {}\nwords {}\n", IMAGE_VERSION, sml.lines().count());
+        for word in sml.lines() {
+            out.push_str(word);
+            out.push('\n');
+        }
+        for (offset, field) in &self.relocs {
+            let field = match field {
+                Field::Operand => "operand",
+                Field::Word => "word",
+                Field::Relative => continue,
+            };
+            out.push_str(&format!("reloc {} {}\n", offset, field));
+        }
+        out
+    }
+}
+
+// places object files into one image, the code of every module in the order given from 0,
+// then the data of every module after all the code
+//
+// a module's .global labels can be used by the others through .extern, the simulator starts
+// at 0 so a jmp to the .entry is put there when the entry is not the first word of code
+pub fn link(modules: &[(String, Object)]) -> Result<Linked, Vec<(AsmblerError, String)>> {
+    let mut errors = Vec::new();
+
+    // (module, section, offset)
+    let mut entry = None;
+    for (index, (name, object)) in modules.iter().enumerate() {
+        if let Some((section, offset)) = object.entry {
+            if entry.is_some() {
+                errors.push((AsmblerError::EntryAlreadyDefined, name.clone()));
+            } else {
+                entry = Some((index, section, offset));
+            }
+        }
+    }
+    let first_code = modules.iter().position(|(_, object)| !object.code.words.is_empty());
+    let jump_to_entry = match entry {
+        Some((index, section, offset)) => Some(index) != first_code || section != Section::Code || offset != 0,
+        None => false,
+    };
+
+    // bases[module] = (code base, data base)
+    let mut bases = vec![(0, 0); modules.len()];
+    let mut cursor: u32 = if jump_to_entry { 1 } else { 0 };
+    for section in [Section::Code, Section::Data] {
+        for (index, (_, object)) in modules.iter().enumerate() {
+            let segment = object.segment(section);
+            let base = cursor.div_ceil(segment.align.max(1)) * segment.align.max(1);
+            match section {
+                Section::Code => bases[index].0 = base,
+                Section::Data => bases[index].1 = base,
+            }
+            cursor = base + segment.words.len() as u32;
+        }
+    }
+    let base_of = |index: usize, section: Section| match section {
+        Section::Code => bases[index].0,
+        Section::Data => bases[index].1,
+    };
+
+    // only the .global labels, two modules can each have a local label of the same name
+    let mut labels = BTreeMap::new();
+    for (index, (name, object)) in modules.iter().enumerate() {
+        for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+            let addr = base_of(index, symbol.section) + symbol.offset;
+            if labels.insert(symbol.name.clone(), addr).is_some() {
+                errors.push((AsmblerError::GlobalAlreadyDefined(symbol.name.clone()), name.clone()));
+            }
+        }
+    }
+
+    let mut words = BTreeMap::new();
+    let mut regions = Vec::new();
+    let mut relocs = Vec::new();
+    let mut sources = Sources::default();
+
+    if let Some((index, section, offset)) = entry
+        && jump_to_entry
+    {
+        match encode_instruction(isa::opcode_of("BRANCH"), (base_of(index, section) + offset) as i32) {
+            Ok(word) => {
+                words.insert(0, PlacedWord { word, line_num: None, is_data: false });
+                relocs.push((0, Field::Operand));
+                regions.push(MemoryRegion { name: "entry".to_string(), start: 0, end: 0 });
+            }
+            Err(e) => errors.push((e, modules[index].0.clone())),
+        }
+    }
+
+    for (index, (name, object)) in modules.iter().enumerate() {
+        let mut segments = [object.code.words.clone(), object.data.words.clone()];
+
+        for reloc in &object.relocs {
+            let base = match &reloc.target {
+                Target::Section(section) => base_of(index, *section),
+                Target::Symbol(symbol) => match labels.get(symbol) {
+                    Some(&addr) => addr,
+                    None => {
+                        errors.push((AsmblerError::UndefinedLabel(symbol.clone()), name.clone()));
+                        continue;
+                    }
+                },
+            };
+            let addr = base_of(index, reloc.section) + reloc.offset;
+            let word = &mut segments[reloc.section as usize][reloc.offset as usize];
+            let moved = match reloc.field {
+                Field::Operand => {
+                    let (opcode, operand) = decode(*word);
+                    encode_instruction(opcode, operand + base as i32)
+                }
+                Field::Word => encode_data(*word + base as i32),
+                Field::Relative => {
+                    let (opcode, operand) = decode(*word);
+                    encode_instruction(opcode, operand + base as i32 - addr as i32)
+                }
+            };
+            match moved {
+                Ok(moved) => *word = moved,
+                Err(e) => errors.push((e, name.clone())),
+            }
+            // the distance stays the same wherever the image is loaded
+            if reloc.field != Field::Relative {
+                relocs.push((addr, reloc.field));
+            }
+        }
+
+        let mut lines = BTreeMap::new();
+        for info in &object.lines {
+            lines.insert((info.section as usize, info.offset), sources.add(&info.file, info.line.saturating_sub(1)));
+        }
+
+        for (section, segment) in [Section::Code, Section::Data].into_iter().zip(segments) {
+            if segment.is_empty() {
+                continue;
+            }
+            let base = base_of(index, section);
+            let end = base + segment.len() as u32 - 1;
+            if end >= MEMORY_SIZE {
+                errors.push((AsmblerError::MemoryRequestOutOfBounds, name.clone()));
+                continue;
+            }
+            for (offset, word) in segment.into_iter().enumerate() {
+                let line_num = lines.get(&(section as usize, offset as u32)).copied();
+                words.insert(base + offset as u32, PlacedWord { word, line_num, is_data: section == Section::Data });
+            }
+            regions.push(MemoryRegion { name: section.name().to_string(), start: base, end });
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    regions.sort_by_key(|region| region.start);
+    relocs.sort_by_key(|&(offset, _)| offset);
+    Ok(Linked { image: Image { words, regions, labels }, relocs, sources })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::{Reloc, Segment, Symbol};
+
+    fn global(name: &str, section: Section, offset: u32) -> Symbol {
+        Symbol { name: name.to_string(), section, offset, global: true }
+    }
+
+    fn reloc(section: Section, offset: u32, field: Field, target: Target) -> Reloc {
+        Reloc { section, offset, field, target }
+    }
+
+    // b: hlt, value: dw 5
+    fn library() -> Object {
+        Object {
+            code: Segment { words: vec![450000], align: 1 },
+            data: Segment { words: vec![5], align: 1 },
+            symbols: vec![global("value", Section::Data, 0)],
+            ..Object::default()
+        }
+    }
+
+    // a: mov acc [count], jmpr done, mov [value] acc, done: hlt, count: dw done
+    fn program() -> Object {
+        Object {
+            code: Segment { words: vec![200000, 600003, 250000, 450000], align: 1 },
+            data: Segment { words: vec![3], align: 1 },
+            externs: vec!["value".to_string()],
+            relocs: vec![
+                reloc(Section::Code, 0, Field::Operand, Target::Section(Section::Data)),
+                reloc(Section::Code, 1, Field::Relative, Target::Section(Section::Code)),
+                reloc(Section::Code, 2, Field::Operand, Target::Symbol("value".to_string())),
+                reloc(Section::Data, 0, Field::Word, Target::Section(Section::Code)),
+            ],
+            ..Object::default()
+        }
+    }
+
+    fn words(linked: &Linked) -> Vec<(u32, i32)> {
+        linked.image.words.iter().map(|(&addr, placed)| (addr, placed.word)).collect()
+    }
+
+    #[test]
+    fn relocations() {
+        let linked = link(&[("b".to_string(), library()), ("a".to_string(), program())]).unwrap();
+        // b's code at 0, a's code at 1, b's data at 5, a's data at 6
+        assert_eq!(words(&linked), vec![(0, 450000), (1, 200006), (2, 600002), (3, 250005), (4, 450000), (5, 5), (6, 4)]);
+        // a relative branch does not move with the image
+        assert_eq!(linked.relocs, vec![(1, Field::Operand), (3, Field::Operand), (6, Field::Word)]);
+        assert_eq!(linked.image.labels.get("value"), Some(&5));
+    }
+
+    #[test]
+    fn undefined_global() {
+        let errors = link(&[("a".to_string(), program())]).err().unwrap();
+        assert_eq!(errors, vec![(AsmblerError::UndefinedLabel("value".to_string()), "a".to_string())]);
+    }
+
+    #[test]
+    fn duplicate_global() {
+        let errors = link(&[("b".to_string(), library()), ("c".to_string(), library())]).err().unwrap();
+        assert_eq!(errors, vec![(AsmblerError::GlobalAlreadyDefined("value".to_string()), "c".to_string())]);
+    }
+}
@@ -0,0 +1,42 @@
+use crate::encoder::{decode, format_word, opcode_name};
+use crate::extensions::Extensions;
+use crate::layout::{Image, PAGE_SIZE};
+
+// a row per word with the line it came from, then the labels and the data words,
+// laid out so it can be read next to a HALT core dump
+pub fn listing(image: &Image, contents: &str, extensions: &Extensions) -> String {
+    let lines: Vec<&str> = contents.split('\n').map(|line| line.trim()).collect();
+    let mut out = String::new();
+
+    out.push_str("ADDR  WORD     OPCODE         LINE  SOURCE\n");
+    for (&addr, placed) in &image.words {
+        let opcode = if placed.is_data {
+            "DATA".to_string()
+        } else {
+            opcode_name(decode(placed.word).0, extensions).unwrap_or_else(|| "??".to_string())
+        };
+        let (line, source) = match placed.line_num {
+            Some(line_num) => ((line_num + 1).to_string(), lines.get(line_num).copied().unwrap_or("")),
+            None => ("-".to_string(), "; added by the assembler"),
+        };
+        out.push_str(&format!("{:04}  {}  {:<13}  {:>4}  {}\n", addr, format_word(placed.word), opcode, line, source));
+    }
+
+    out.push_str("\nLABELS\n");
+    let mut labels: Vec<(&String, &u32)> = image.labels.iter().collect();
+    labels.sort_by_key(|&(name, &addr)| (addr, name.clone()));
+    for (name, addr) in &labels {
+        out.push_str(&format!("{:04}  {}\n", addr, name));
+    }
+
+    out.push_str("\nDATA\n");
+    for (&addr, placed) in image.words.iter().filter(|(_, placed)| placed.is_data) {
+        let names: Vec<&str> = labels.iter()
+            .filter(|&&(_, &label_addr)| label_addr == addr)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        out.push_str(&format!("{:04}  {}  page {:02}  {}\n", addr, format_word(placed.word), addr / PAGE_SIZE, names.join(" ")));
+    }
+
+    out
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use crate::asmbler::AsmblerError;
+use crate::parser::{ParseError, Statement, StatementKind, Term};
+
+// .loop labels and numeric 1: labels, given names of their own before assembly
+//
+// .loop belongs to the last global label before it, after top: it is top.loop, so every routine
+// can have its own .loop
+// 1: can be defined any number of times, 1b is the last 1: at or before the line and 1f the next
+// one after it, each definition becomes __1_0, __1_1 ...
+
+pub fn resolve(statements: Vec<Result<Statement, ParseError>>) -> Vec<Result<Statement, ParseError>> {
+
+    // index of every statement that defines each number, in order
+    let mut numeric: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (index, statement) in statements.iter().enumerate() {
+        for label in labels_of(statement) {
+            if let Ok(number) = label.parse::<u32>() {
+                numeric.entry(number).or_default().push(index);
+            }
+        }
+    }
+
+    let mut scope = String::new();
+    let mut out = Vec::new();
+
+    for (index, statement) in statements.into_iter().enumerate() {
+        // the global labels of this line start a new scope before its own .labels are named
+        for label in labels_of(&statement) {
+            if !label.starts_with('.') && label.parse::<u32>().is_err() {
+                scope = label.clone();
+            }
+        }
+        if let Ok(Statement { kind: Some(StatementKind::Instruction { mnemonic, operands }), .. }) = &statement
+            && mnemonic == "proc"
+            && let [Term::Symbol(name)] = operands.as_slice()
+        {
+            scope = name.clone();
+        }
+
+        let names = Names { scope: &scope, numeric: &numeric, index };
+        let statement = match statement {
+            Ok(statement) => names.statement(statement),
+            Err(ParseError { error, line_num, labels }) => {
+                let labels = labels.iter().map(|label| names.definition(label)).collect();
+                Err(ParseError { error, line_num, labels })
+            }
+        };
+        out.push(statement);
+    }
+
+    out
+}
+
+fn labels_of(statement: &Result<Statement, ParseError>) -> &[String] {
+    match statement {
+        Ok(statement) => &statement.labels,
+        Err(e) => &e.labels,
+    }
+}
+
+struct Names<'a> {
+    scope: &'a str,
+    numeric: &'a HashMap<u32, Vec<usize>>,
+    // the statement being named
+    index: usize,
+}
+
+impl Names<'_> {
+    fn statement(&self, statement: Statement) -> Result<Statement, ParseError> {
+        let labels: Vec<String> = statement.labels.iter().map(|label| self.definition(label)).collect();
+        let error = |error| Err(ParseError { error, line_num: statement.line_num, labels: labels.clone() });
+
+        let kind = match statement.kind {
+            Some(StatementKind::Instruction { mnemonic, operands }) => match self.terms(operands) {
+                Ok(operands) => Some(StatementKind::Instruction { mnemonic, operands }),
+                Err(e) => return error(e),
+            },
+            Some(StatementKind::Directive { name, args }) => match self.terms(args) {
+                Ok(args) => Some(StatementKind::Directive { name, args }),
+                Err(e) => return error(e),
+            },
+            None => None,
+        };
+
+        Ok(Statement { line_num: statement.line_num, labels, kind })
+    }
+
+    fn definition(&self, label: &str) -> String {
+        if let Ok(number) = label.parse::<u32>() {
+            let position = self.numeric[&number].iter().position(|&index| index == self.index).unwrap_or(0);
+            return numeric_name(number, position);
+        }
+        self.local(label)
+    }
+
+    fn local(&self, label: &str) -> String {
+        match label.strip_prefix('.') {
+            Some(name) if !self.scope.is_empty() => format!("{}.{}", self.scope, name),
+            _ => label.to_string(),
+        }
+    }
+
+    fn terms(&self, terms: Vec<Term>) -> Result<Vec<Term>, AsmblerError> {
+        terms.into_iter().map(|term| self.term(term)).collect()
+    }
+
+    fn term(&self, term: Term) -> Result<Term, AsmblerError> {
+        let inner = |term: Box<Term>| self.term(*term).map(Box::new);
+        Ok(match term {
+            Term::Symbol(name) => Term::Symbol(self.local(&name)),
+            Term::NumericRef(number, forward) => Term::Symbol(self.reference(number, forward)?),
+            Term::AddressOf(term) => Term::AddressOf(inner(term)?),
+            Term::Memory(term) => Term::Memory(inner(term)?),
+            Term::Offset(base, offset) => Term::Offset(inner(base)?, inner(offset)?),
+            Term::Range(first, last) => Term::Range(inner(first)?, inner(last)?),
+            Term::Call(name, arg) => Term::Call(name, inner(arg)?),
+            Term::Compare(left, op, right) => Term::Compare(inner(left)?, op, inner(right)?),
+            Term::Register(_) | Term::Number(_) | Term::Str(_) => term,
+        })
+    }
+
+    fn reference(&self, number: u32, forward: bool) -> Result<String, AsmblerError> {
+        let definitions = self.numeric.get(&number).map_or(&[][..], |indexes| indexes.as_slice());
+        let position = if forward {
+            definitions.iter().position(|&index| index > self.index)
+        } else {
+            definitions.iter().rposition(|&index| index <= self.index)
+        };
+        match position {
+            Some(position) => Ok(numeric_name(number, position)),
+            None => Err(AsmblerError::UndefinedLabel(format!("{}{}", number, if forward { 'f' } else { 'b' }))),
+        }
+    }
+}
+
+fn numeric_name(number: u32, position: usize) -> String {
+    format!("__{}_{}", number, position)
+}
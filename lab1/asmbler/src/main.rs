@@ -1,28 +1,185 @@
+use std::collections::BTreeMap;
+
 use clap::Parser;
 
 
+mod analysis;
 mod asmbler;
+mod conditional;
+mod disasm;
+mod encoder;
+mod extensions;
+mod include;
+mod layout;
+mod lexer;
+mod linker;
+mod listing;
+mod locals;
+mod object;
+mod optimize;
+mod parser;
+mod procs;
+mod structured;
+mod symbols;
+use analysis::{Cell, Kind};
 use asmbler::Asmbler;
+use object::Object;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(short, long)]
-    input_file: String,
+    #[arg(short, long, required_unless_present = "link")]
+    input_file: Option<String>,
     #[arg(short, long, default_value = "output.sml")]
     output_file: String,
+    /// print which pages each section occupies
+    #[arg(short, long)]
+    memory_map: bool,
+    /// write a listing of every word with the source line it came from
+    #[arg(short, long)]
+    listing_file: Option<String>,
+    /// write the addresses of every source line and label for the simulator, with --link only the .global labels
+    #[arg(short, long)]
+    symbols_file: Option<String>,
+    /// a file of .opcode lines for extension instructions, as if they were at the top of the input
+    #[arg(short = 'p', long)]
+    isa_profile: Option<String>,
+    /// do not end the code with a hlt, for programs that always halt on their own
+    #[arg(long)]
+    no_implicit_hlt: bool,
+    /// position independent code, jmp, jlz and jez to labels are written relative to the branch
+    #[arg(long)]
+    pic: bool,
+    /// take out redundant loads, stores, swaps and jumps, every change is printed, the output and
+    /// registers stay the same only when code reaches words through labels, not .org or jmp 12
+    #[arg(short = 'O', long)]
+    optimize: bool,
+    /// NAME=value for .ifdef, .ifndef and .if, NAME on its own is 1
+    #[arg(short = 'D', value_name = "NAME=value")]
+    define: Vec<String>,
+    /// warn about unreachable code, running off the end or into data, reads of memory nothing
+    /// wrote, stores into code and division by 0, an .sml input is checked without assembling
+    #[arg(short, long)]
+    analyze: bool,
+    /// write the control flow graph as Graphviz DOT, implies --analyze
+    #[arg(long, value_name = "FILE")]
+    cfg: Option<String>,
+    /// turn the .sml input file back into asml
+    #[arg(short, long)]
+    disassemble: bool,
+    /// write an object file with its .global and .extern labels for --link instead of a program
+    #[arg(short = 'c', long)]
+    object: bool,
+    /// link object files into one program, their code is placed in the order given
+    #[arg(long, num_args = 1.., conflicts_with = "input_file")]
+    link: Vec<String>,
+    /// with --link, write an image the simulator can load at any address instead of plain words
+    #[arg(short, long, requires = "link")]
+    relocatable: bool,
 }
 
 fn main() {
     let args = Args::parse();
 
-    let input = std::fs::read_to_string(&args.input_file)
+    if !args.link.is_empty() {
+        link(&args);
+        return;
+    }
+    let input_file = args.input_file.clone().unwrap_or_default();
+
+    let input = std::fs::read_to_string(&input_file)
         .expect("Failed to read input file");
 
-    let result = Asmbler::assemble(input, vec![]);
+    if (args.analyze || args.cfg.is_some()) && input_file.ends_with(".sml") {
+        match disasm::sml_lines(&input) {
+            Ok(words) => {
+                let lines: Vec<&str> = input.lines().collect();
+                let cells = words.iter().enumerate()
+                    .map(|(addr, &(line_num, word))| (addr as u32, Cell { word, kind: Kind::Unknown, line_num: Some(line_num), labelled: true }))
+                    .collect();
+                analyze(&args, &cells, &BTreeMap::new(), |line_num| (format!("line {}", line_num + 1), lines[line_num].trim().to_string()));
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.disassemble {
+        match disasm::parse_sml(&input) {
+            Ok(words) => {
+                std::fs::write(&args.output_file, disasm::disassemble(&words))
+                    .expect("Failed to write output file");
+                println!("Disassembly successful! Output written to {}", args.output_file);
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut flags = vec![format!("--source={}", input_file)];
+    if args.object {
+        flags.push("--object".to_string());
+    }
+    if args.pic {
+        flags.push("--pic".to_string());
+    }
+    if args.optimize {
+        flags.push("--optimize".to_string());
+    }
+    if args.no_implicit_hlt {
+        flags.push("--no-implicit-hlt".to_string());
+    }
+    flags.extend(args.define.iter().map(|define| format!("-D{}", define)));
+    if let Some(profile_file) = &args.isa_profile {
+        let profile = std::fs::read_to_string(profile_file)
+            .expect("Failed to read ISA profile");
+        match profile_flags(&profile) {
+            Ok(opcodes) => flags.extend(opcodes),
+            Err(e) => {
+                println!("Error in ISA profile {}: {}", profile_file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let result = Asmbler::assemble(input, flags);
 
     if result.has_errors() {
         result.print_errors();
+        std::process::exit(1);
+    }
+
+    result.print_optimizations();
+
+    if args.memory_map {
+        print!("{}", result.memory_map_report());
+    }
+
+    if let (true, Some(image)) = (args.analyze || args.cfg.is_some(), &result.image) {
+        let labelled: std::collections::HashSet<u32> = image.labels.values().copied().collect();
+        let cells = image.words.iter()
+            .map(|(&addr, placed)| {
+                let kind = if placed.is_data { Kind::Data } else { Kind::Code };
+                (addr, Cell { word: placed.word, kind, line_num: placed.line_num, labelled: labelled.contains(&addr) })
+            })
+            .collect();
+        analyze(&args, &cells, &image.labels, |line_num| result.location(line_num));
+    }
+
+    if let (Some(listing_file), Some(_)) = (&args.listing_file, &result.image) {
+        std::fs::write(listing_file, &result.results)
+            .expect("Failed to write listing file");
+    }
+
+    if let (Some(symbols_file), Some(image)) = (&args.symbols_file, &result.image) {
+        std::fs::write(symbols_file, symbols::symbol_file(image, &result.sources))
+            .expect("Failed to write symbols file");
     }
 
     std::fs::write(&args.output_file, result.contents)
@@ -30,4 +187,83 @@ fn main() {
 
     println!("Assembly successful! Output written to {}", args.output_file);
 
-}
\ No newline at end of file
+}
+
+fn link(args: &Args) {
+    let mut modules = Vec::new();
+    for object_file in &args.link {
+        let contents = std::fs::read_to_string(object_file)
+            .expect("Failed to read object file");
+        match Object::parse(&contents) {
+            Ok(object) => modules.push((object_file.clone(), object)),
+            Err(e) => {
+                println!("Error in {}: {}", object_file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let linked = match linker::link(&modules) {
+        Ok(linked) => linked,
+        Err(errors) => {
+            for (error, module) in errors {
+                println!("Error in {}: {:?}", module, error);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    if args.memory_map {
+        print!("{}", layout::memory_map_report(&linked.image.regions));
+    }
+
+    if let Some(symbols_file) = &args.symbols_file {
+        std::fs::write(symbols_file, symbols::symbol_file(&linked.image, &linked.sources))
+            .expect("Failed to write symbols file");
+    }
+
+    let output = if args.relocatable { linked.to_image_text() } else { linked.image.to_sml() };
+    std::fs::write(&args.output_file, output)
+        .expect("Failed to write output file");
+
+    println!("Link successful! Output written to {}", args.output_file);
+}
+
+// prints the warnings the way errors are printed, location gives "line 3" and the source line
+fn analyze(args: &Args, cells: &BTreeMap<u32, Cell>, labels: &BTreeMap<String, u32>, location: impl Fn(usize) -> (String, String)) {
+    let analysis = analysis::analyze(cells);
+    for (warning, addr) in &analysis.warnings {
+        match cells.get(addr).and_then(|cell| cell.line_num) {
+            Some(line_num) => {
+                let (place, line) = location(line_num);
+                println!("Warning on {}: {:?} - {}", place, warning, line);
+            }
+            None => println!("Warning at address {}: {:?}", addr, warning),
+        }
+    }
+    if analysis.warnings.is_empty() {
+        println!("No warnings");
+    }
+
+    if let Some(cfg_file) = &args.cfg {
+        std::fs::write(cfg_file, analysis::to_dot(&analysis, cells, labels))
+            .expect("Failed to write CFG file");
+        println!("Control flow graph written to {}", cfg_file);
+    }
+}
+
+// .opcode lines become --opcode= flags, ; comments and blank lines are skipped
+fn profile_flags(profile: &str) -> Result<Vec<String>, String> {
+    let mut flags = Vec::new();
+    for (line_num, line) in profile.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.strip_prefix(".opcode ") {
+            Some(spec) => flags.push(format!("--opcode={}", spec.trim())),
+            None => return Err(format!("line {} is not an .opcode line: {}", line_num + 1, line)),
+        }
+    }
+    Ok(flags)
+}
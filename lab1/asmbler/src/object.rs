@@ -0,0 +1,295 @@
+use crate::encoder::format_word;
+use crate::layout::Section;
+
+// an assembled module the linker can place anywhere, written with --object
+//
+// simpletron object <version>
+// code <words> <align>        followed by one word per line
+// data <words> <align>        followed by one word per line
+// symbol global|local <name> code|data <offset>
+// extern <name>
+// reloc code|data <offset> operand|word|relative code|data     - add the base of that section of the module
+// reloc code|data <offset> operand|word|relative extern <name> - add the address of the label
+// entry code|data <offset>
+// line code|data <offset> <line> <file>  - the source line a word came from
+//
+// a reloc against a section already holds the offset in it, against an extern it holds 0
+// a relative reloc is on the operand of a jmpr, jlzr or jezr and takes off the address of the word
+// the version changes whenever a record changes meaning, older files are refused rather than misread
+
+pub const OBJECT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Segment {
+    pub words: Vec<i32>,
+    // the linker places the segment on a multiple of this
+    pub align: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: Section,
+    pub offset: u32,
+    // .global, other modules can use it
+    pub global: bool,
+}
+
+// the part of the word that holds the address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    // the operand of an instruction
+    Operand,
+    // the whole word, dw label
+    Word,
+    // the operand of a pc relative branch
+    Relative,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Section(Section),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reloc {
+    pub section: Section,
+    pub offset: u32,
+    pub field: Field,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineInfo {
+    pub section: Section,
+    pub offset: u32,
+    // from 1, as in the file
+    pub line: usize,
+    pub file: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Object {
+    pub code: Segment,
+    pub data: Segment,
+    pub symbols: Vec<Symbol>,
+    pub externs: Vec<String>,
+    pub relocs: Vec<Reloc>,
+    pub entry: Option<(Section, u32)>,
+    pub lines: Vec<LineInfo>,
+}
+
+impl Object {
+    pub fn segment(&self, section: Section) -> &Segment {
+        match section {
+            Section::Code => &self.code,
+            Section::Data => &self.data,
+        }
+    }
+
+    pub fn segment_mut(&mut self, section: Section) -> &mut Segment {
+        match section {
+            Section::Code => &mut self.code,
+            Section::Data => &mut self.data,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = format!("simpletron object {}\n", OBJECT_VERSION);
+        for section in [Section::Code, Section::Data] {
+            let segment = self.segment(section);
+            out.push_str(&format!("{} {} {}\n", section.name(), segment.words.len(), segment.align));
+            for &word in &segment.words {
+                out.push_str(&format!("{}\n", format_word(word)));
+            }
+        }
+        for symbol in &self.symbols {
+            let kind = if symbol.global { "global" } else { "local" };
+            out.push_str(&format!("symbol {} {} {} {}\n", kind, symbol.name, symbol.section.name(), symbol.offset));
+        }
+        for name in &self.externs {
+            out.push_str(&format!("extern {}\n", name));
+        }
+        for reloc in &self.relocs {
+            let field = match reloc.field {
+                Field::Operand => "operand",
+                Field::Word => "word",
+                Field::Relative => "relative",
+            };
+            let target = match &reloc.target {
+                Target::Section(section) => section.name().to_string(),
+                Target::Symbol(name) => format!("extern {}", name),
+            };
+            out.push_str(&format!("reloc {} {} {} {}\n", reloc.section.name(), reloc.offset, field, target));
+        }
+        if let Some((section, offset)) = self.entry {
+            out.push_str(&format!("entry {} {}\n", section.name(), offset));
+        }
+        for info in &self.lines {
+            out.push_str(&format!("line {} {} {} {}\n", info.section.name(), info.offset, info.line, info.file));
+        }
+        out
+    }
+
+    pub fn parse(contents: &str) -> Result<Object, String> {
+        let mut object = Object::default();
+        let mut lines = contents.lines().enumerate()
+            .map(|(line_num, line)| (line_num, line.split(';').next().unwrap_or("").trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        match lines.next().map(|(_, line)| line.split_whitespace().collect::<Vec<&str>>()).as_deref() {
+            Some(["simpletron", "object", version]) => match version.parse::<u32>() {
+                Ok(OBJECT_VERSION) => {}
+                _ => return Err(format!("Object version {} is not supported, this assembler reads version {}", version, OBJECT_VERSION)),
+            },
+            _ => return Err("Not a simpletron object file".to_string()),
+        }
+
+        while let Some((line_num, line)) = lines.next() {
+            let invalid = || format!("Invalid object record on line {}: {}", line_num + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [section @ ("code" | "data"), count, align] => {
+                    let section = parse_section(section).ok_or_else(invalid)?;
+                    let count: usize = count.parse().map_err(|_| invalid())?;
+                    let align: u32 = align.parse().map_err(|_| invalid())?;
+                    if align == 0 {
+                        return Err(invalid());
+                    }
+                    let mut words = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        let (word_line, word) = lines.next().ok_or_else(|| format!("Missing {} words after line {}", section.name(), line_num + 1))?;
+                        words.push(word.parse::<i32>().map_err(|_| format!("Invalid word on line {}: {}", word_line + 1, word))?);
+                    }
+                    *object.segment_mut(section) = Segment { words, align };
+                }
+                ["symbol", kind @ ("global" | "local"), name, section, offset] => object.symbols.push(Symbol {
+                    name: name.to_string(),
+                    section: parse_section(section).ok_or_else(invalid)?,
+                    offset: offset.parse().map_err(|_| invalid())?,
+                    global: *kind == "global",
+                }),
+                ["extern", name] => object.externs.push(name.to_string()),
+                ["reloc", section, offset, field, target @ ..] => {
+                    let field = match *field {
+                        "operand" => Field::Operand,
+                        "word" => Field::Word,
+                        "relative" => Field::Relative,
+                        _ => return Err(invalid()),
+                    };
+                    let target = match target {
+                        ["extern", name] => Target::Symbol(name.to_string()),
+                        [section] => Target::Section(parse_section(section).ok_or_else(invalid)?),
+                        _ => return Err(invalid()),
+                    };
+                    object.relocs.push(Reloc {
+                        section: parse_section(section).ok_or_else(invalid)?,
+                        offset: offset.parse().map_err(|_| invalid())?,
+                        field,
+                        target,
+                    });
+                }
+                // the file name is the rest of the line
+                ["line", section, offset, source_line, file @ ..] if !file.is_empty() => object.lines.push(LineInfo {
+                    section: parse_section(section).ok_or_else(invalid)?,
+                    offset: offset.parse().map_err(|_| invalid())?,
+                    line: source_line.parse().map_err(|_| invalid())?,
+                    file: file.join(" "),
+                }),
+                ["entry", section, offset] => {
+                    object.entry = Some((parse_section(section).ok_or_else(invalid)?, offset.parse().map_err(|_| invalid())?));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        // everything that points into a segment has to land inside it
+        for symbol in &object.symbols {
+            if symbol.offset > object.segment(symbol.section).words.len() as u32 {
+                return Err(format!("Symbol {} is outside the {} segment", symbol.name, symbol.section.name()));
+            }
+        }
+        if let Some((section, offset)) = object.entry
+            && offset >= object.segment(section).words.len() as u32
+        {
+            return Err(format!("Entry at {} {} is outside the segment", section.name(), offset));
+        }
+        for info in &object.lines {
+            if info.offset >= object.segment(info.section).words.len() as u32 {
+                return Err(format!("Line info at {} {} is outside the segment", info.section.name(), info.offset));
+            }
+        }
+        for reloc in &object.relocs {
+            if reloc.offset >= object.segment(reloc.section).words.len() as u32 {
+                return Err(format!("Relocation at {} {} is outside the segment", reloc.section.name(), reloc.offset));
+            }
+            if let Target::Symbol(name) = &reloc.target
+                && !object.externs.contains(name)
+            {
+                return Err(format!("Relocation at {} {} uses {}, which is not an extern", reloc.section.name(), reloc.offset, name));
+            }
+        }
+
+        Ok(object)
+    }
+}
+
+fn parse_section(name: &str) -> Option<Section> {
+    match name {
+        "code" => Some(Section::Code),
+        "data" => Some(Section::Data),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object() -> Object {
+        Object {
+            code: Segment { words: vec![200003, 300000, 430000], align: 1 },
+            data: Segment { words: vec![7, 0], align: 10 },
+            symbols: vec![
+                Symbol { name: "main".to_string(), section: Section::Code, offset: 0, global: true },
+                Symbol { name: "count".to_string(), section: Section::Data, offset: 0, global: false },
+            ],
+            externs: vec!["total".to_string()],
+            relocs: vec![
+                Reloc { section: Section::Code, offset: 0, field: Field::Operand, target: Target::Section(Section::Data) },
+                Reloc { section: Section::Code, offset: 1, field: Field::Operand, target: Target::Symbol("total".to_string()) },
+                Reloc { section: Section::Data, offset: 1, field: Field::Word, target: Target::Section(Section::Code) },
+            ],
+            entry: Some((Section::Code, 0)),
+            lines: vec![LineInfo { section: Section::Code, offset: 2, line: 12, file: "my prog.asml".to_string() }],
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let object = object();
+        assert_eq!(Object::parse(&object.to_text()), Ok(object));
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        let text = object().to_text().replacen(&format!("object {}", OBJECT_VERSION), &format!("object {}", OBJECT_VERSION + 1), 1);
+        assert!(Object::parse(&text).unwrap_err().starts_with("Object version"));
+        assert_eq!(Object::parse("code 0 1\n"), Err("Not a simpletron object file".to_string()));
+    }
+
+    #[test]
+    fn reloc_against_a_name_that_is_not_an_extern() {
+        let mut object = object();
+        object.externs.clear();
+        assert_eq!(Object::parse(&object.to_text()), Err("Relocation at code 1 uses total, which is not an extern".to_string()));
+    }
+
+    #[test]
+    fn entry_outside_the_segment() {
+        let mut object = object();
+        object.entry = Some((Section::Data, 2));
+        assert_eq!(Object::parse(&object.to_text()), Err("Entry at data 2 is outside the segment".to_string()));
+    }
+}
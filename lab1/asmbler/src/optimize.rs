@@ -0,0 +1,472 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{ParseError, Statement, StatementKind, Term};
+
+// -O, a peephole pass over the statements once procs and blocks are expanded, before layout
+//
+// - mov [x] acc then mov acc [x]: the load goes, acc already holds the value
+// - mov acc [x] then mov [x] acc: the store goes, the cell already holds acc
+// - a load of acc followed by another load of acc: the first goes
+// - swap then swap: both go
+// - jmp l straight before l: the jmp goes
+// - a branch to a jmp m goes to m, unless the jmp is read or written as data
+// - a store to a dw cell that nothing else refers to goes, only when nothing is reached
+//   through idx or a pointer, which could read the cell without naming it
+//
+// a line with a label that is used somewhere is never taken out from behind the line before it,
+// something could jump to it, the labels of a line that goes are kept on the next one
+// code that jumps to fixed addresses, ie mov acc 37, swap, jmp idx, breaks once lines go
+// every change is reported with the line it came from
+
+pub struct Change {
+    pub line_num: usize,
+    pub description: String,
+}
+
+pub fn optimize(statements: Vec<Result<Statement, ParseError>>) -> (Vec<Result<Statement, ParseError>>, Vec<Change>) {
+    // a program with errors is not assembled, there is nothing to gain
+    if statements.iter().any(|statement| statement.is_err()) {
+        return (statements, Vec::new());
+    }
+    let mut statements: Vec<Statement> = statements.into_iter().map(|statement| statement.expect("checked above")).collect();
+    let mut changes = Vec::new();
+
+    // each change takes a line out or moves a branch nearer its end, so this stops
+    while pairs(&mut statements, &mut changes) || thread_jumps(&mut statements, &mut changes) || dead_stores(&mut statements, &mut changes) {}
+
+    (statements.into_iter().map(Ok).collect(), changes)
+}
+
+fn instruction(statement: &Statement) -> Option<(&str, &[Term])> {
+    match &statement.kind {
+        Some(StatementKind::Instruction { mnemonic, operands }) => Some((mnemonic.as_str(), operands.as_slice())),
+        _ => None,
+    }
+}
+
+fn is_acc(term: &Term) -> bool {
+    matches!(term, Term::Register(name) if name == "acc")
+}
+
+// a cell named directly or through idx, [[x]] goes through a pointer that the store could change
+fn is_cell(term: &Term) -> bool {
+    matches!(term, Term::Memory(inner) if !matches!(inner.as_ref(), Term::Memory(_)))
+}
+
+// mov [x] acc
+fn stored_cell(statement: &Statement) -> Option<&Term> {
+    match instruction(statement) {
+        Some(("mov", [cell, acc])) if is_cell(cell) && is_acc(acc) => Some(cell),
+        _ => None,
+    }
+}
+
+// mov acc [x], mov acc 5, mov acc &label - acc is set without being read
+fn loads_acc(statement: &Statement) -> bool {
+    matches!(instruction(statement), Some(("mov", [acc, value])) if is_acc(acc) && !matches!(value, Term::Register(_)))
+}
+
+fn loaded_cell(statement: &Statement) -> Option<&Term> {
+    match instruction(statement) {
+        Some(("mov", [acc, cell])) if is_acc(acc) && is_cell(cell) => Some(cell),
+        _ => None,
+    }
+}
+
+fn text(statement: &Statement) -> String {
+    match instruction(statement) {
+        Some((mnemonic, operands)) => {
+            let operands: Vec<String> = operands.iter().map(term_text).collect();
+            format!("{} {}", mnemonic, operands.join(" ")).trim_end().to_string()
+        }
+        None => String::new(),
+    }
+}
+
+fn term_text(term: &Term) -> String {
+    match term {
+        Term::Register(name) | Term::Symbol(name) => name.clone(),
+        Term::Number(value) => value.to_string(),
+        Term::Str(text) => format!("{:?}", text),
+        Term::Memory(inner) => format!("[{}]", term_text(inner)),
+        Term::Offset(base, offset) => format!("{}+{}", term_text(base), term_text(offset)),
+        Term::Range(first, last) => format!("{}..{}", term_text(first), term_text(last)),
+        Term::Call(name, arg) => format!("{}({})", name, term_text(arg)),
+        Term::AddressOf(inner) => format!("&{}", term_text(inner)),
+        Term::NumericRef(number, forward) => format!("{}{}", number, if *forward { 'f' } else { 'b' }),
+        Term::Compare(left, op, right) => format!("{} {} {}", term_text(left), op, term_text(right)),
+    }
+}
+
+// takes out statement index, its labels go on the statement after it
+fn remove(statements: &mut Vec<Statement>, index: usize) {
+    let removed = statements.remove(index);
+    if removed.labels.is_empty() {
+        return;
+    }
+    match statements.get_mut(index) {
+        Some(next) => {
+            let mut labels = removed.labels;
+            labels.append(&mut next.labels);
+            next.labels = labels;
+        }
+        None => statements.push(Statement { line_num: removed.line_num, labels: removed.labels, kind: None }),
+    }
+}
+
+// the instruction after index when nothing can jump to it, labels nothing uses are skipped
+fn next_instruction(statements: &[Statement], index: usize, used: &HashSet<String>) -> Option<usize> {
+    for (next, statement) in statements.iter().enumerate().skip(index + 1) {
+        if statement.labels.iter().any(|label| used.contains(label)) {
+            return None;
+        }
+        if statement.kind.is_some() {
+            return Some(next);
+        }
+    }
+    None
+}
+
+// two instructions next to each other, with nothing that could jump between them
+fn pairs(statements: &mut Vec<Statement>, changes: &mut Vec<Change>) -> bool {
+    let mut used = HashSet::new();
+    for statement in statements.iter() {
+        match &statement.kind {
+            Some(StatementKind::Instruction { operands: terms, .. }) | Some(StatementKind::Directive { args: terms, .. }) => {
+                terms.iter().for_each(|term| symbols(term, &mut used));
+            }
+            None => {}
+        }
+    }
+
+    for index in 0..statements.len() {
+        let first = &statements[index];
+
+        // jmp l, l:
+        if let Some(("jmp", [Term::Symbol(target)])) = instruction(first) {
+            // the labels up to and on the next line that is not just labels
+            let mut next_labels = Vec::new();
+            for statement in &statements[index + 1..] {
+                next_labels.extend(statement.labels.iter());
+                if statement.kind.is_some() {
+                    break;
+                }
+            }
+            if next_labels.contains(&target) {
+                changes.push(Change { line_num: first.line_num, description: format!("removed jmp {}, it goes to the next line", target) });
+                remove(statements, index);
+                return true;
+            }
+        }
+
+        let Some(next) = next_instruction(statements, index, &used) else { continue };
+        let second = &statements[next];
+        if instruction(first).is_none() || instruction(second).is_none() {
+            continue;
+        }
+
+        let (remove_first, remove_second, description) = if stored_cell(first).is_some() && stored_cell(first) == loaded_cell(second) {
+            (false, true, format!("removed {}, acc already holds the value stored", text(second)))
+        } else if loaded_cell(first).is_some() && loaded_cell(first) == stored_cell(second) {
+            (false, true, format!("removed {}, the cell already holds acc", text(second)))
+        } else if loads_acc(first) && loads_acc(second) {
+            (true, false, format!("removed {}, acc is loaded again straight after", text(first)))
+        } else if text(first) == "swap" && text(second) == "swap" {
+            (true, true, "removed swap swap".to_string())
+        } else {
+            continue;
+        };
+
+        changes.push(Change { line_num: if remove_first { first.line_num } else { second.line_num }, description });
+        if remove_second {
+            remove(statements, next);
+        }
+        if remove_first {
+            remove(statements, index);
+        }
+        return true;
+    }
+    false
+}
+
+// the statement the label is on, or the first after it when it is on a line of its own
+fn labelled(statements: &[Statement], label: &str) -> Option<usize> {
+    let start = statements.iter().position(|statement| statement.labels.iter().any(|l| l == label))?;
+    (start..statements.len()).find(|&index| statements[index].kind.is_some())
+}
+
+// every label that appears anywhere but as the target of jmp, jlz or jez
+fn data_references(statements: &[Statement]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for statement in statements {
+        match &statement.kind {
+            Some(StatementKind::Instruction { mnemonic, operands }) => {
+                if matches!((mnemonic.as_str(), operands.as_slice()), ("jmp" | "jlz" | "jez", [Term::Symbol(_)])) {
+                    continue;
+                }
+                operands.iter().for_each(|term| symbols(term, &mut names));
+            }
+            Some(StatementKind::Directive { args, .. }) => args.iter().for_each(|term| symbols(term, &mut names)),
+            None => {}
+        }
+    }
+    names
+}
+
+fn symbols(term: &Term, names: &mut HashSet<String>) {
+    match term {
+        Term::Symbol(name) => {
+            names.insert(name.clone());
+        }
+        Term::Memory(inner) | Term::AddressOf(inner) | Term::Call(_, inner) => symbols(inner, names),
+        Term::Offset(left, right) | Term::Range(left, right) | Term::Compare(left, _, right) => {
+            symbols(left, names);
+            symbols(right, names);
+        }
+        Term::Register(_) | Term::Number(_) | Term::Str(_) | Term::NumericRef(..) => {}
+    }
+}
+
+fn thread_jumps(statements: &mut [Statement], changes: &mut Vec<Change>) -> bool {
+    let data = data_references(statements);
+
+    for index in 0..statements.len() {
+        let (mnemonic, target) = match instruction(&statements[index]) {
+            Some((mnemonic @ ("jmp" | "jlz" | "jez"), [Term::Symbol(target)])) => (mnemonic.to_string(), target.clone()),
+            _ => continue,
+        };
+
+        // follow jmp to jmp, a loop of them is left alone
+        let mut end = target.clone();
+        let mut seen = HashSet::from([target.clone()]);
+        while let Some(next) = labelled(statements, &end)
+            && !data.contains(&end)
+            && let Some(("jmp", [Term::Symbol(further)])) = instruction(&statements[next])
+        {
+            if !seen.insert(further.clone()) {
+                end = target.clone();
+                break;
+            }
+            end = further.clone();
+        }
+        if end == target {
+            continue;
+        }
+
+        changes.push(Change { line_num: statements[index].line_num, description: format!("{} {} goes straight to {}", mnemonic, target, end) });
+        statements[index].kind = Some(StatementKind::Instruction { mnemonic, operands: vec![Term::Symbol(end)] });
+        return true;
+    }
+    false
+}
+
+fn dead_stores(statements: &mut Vec<Statement>, changes: &mut Vec<Change>) -> bool {
+    // anything reached through idx, a pointer or an instruction the ISA does not know could read
+    // any cell
+    let indirect = statements.iter().filter_map(instruction).any(|(mnemonic, operands)| {
+        mnemonic == "writec"
+            || (mnemonic != "dw" && isa::by_mnemonic(mnemonic).next().is_none())
+            || operands.iter().any(|term| match term {
+                Term::Memory(inner) => matches!(inner.as_ref(), Term::Memory(_) | Term::Offset(..) | Term::Register(_)),
+                _ => false,
+            })
+    });
+    if indirect {
+        return false;
+    }
+
+    // dw cells, with a label on the dw or just before it
+    let mut cells = HashSet::new();
+    for statement in statements.iter() {
+        match instruction(statement) {
+            Some(("dw", [Term::Symbol(name), _])) => {
+                cells.insert(name.clone());
+            }
+            Some(("dw", _)) => cells.extend(statement.labels.iter().cloned()),
+            _ => {}
+        }
+    }
+    let mut pending = Vec::new();
+    for statement in statements.iter() {
+        if statement.kind.is_none() {
+            pending.extend(statement.labels.iter().cloned());
+            continue;
+        }
+        if matches!(instruction(statement), Some(("dw", _))) {
+            cells.extend(pending.drain(..));
+        }
+        pending.clear();
+    }
+
+    // every use of a name other than mov [name] acc
+    let mut used: HashMap<String, usize> = HashMap::new();
+    for statement in statements.iter() {
+        let mut names = HashSet::new();
+        match (&statement.kind, stored_cell(statement)) {
+            (_, Some(Term::Memory(inner))) if matches!(inner.as_ref(), Term::Symbol(_)) => {}
+            (Some(StatementKind::Instruction { mnemonic, operands }), _) => {
+                match (mnemonic.as_str(), operands.as_slice()) {
+                    // dw name value defines name
+                    ("dw", [Term::Symbol(_), value]) => symbols(value, &mut names),
+                    _ => operands.iter().for_each(|term| symbols(term, &mut names)),
+                }
+            }
+            (Some(StatementKind::Directive { args, .. }), _) => args.iter().for_each(|term| symbols(term, &mut names)),
+            (None, _) => {}
+        }
+        for name in names {
+            *used.entry(name).or_default() += 1;
+        }
+    }
+
+    for index in 0..statements.len() {
+        if let Some(Term::Memory(inner)) = stored_cell(&statements[index])
+            && let Term::Symbol(name) = inner.as_ref()
+            && cells.contains(name)
+            && !used.contains_key(name)
+        {
+            changes.push(Change { line_num: statements[index].line_num, description: format!("removed {}, {} is never read", text(&statements[index]), name) });
+            remove(statements, index);
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use sim::simpletron::Simpletron;
+
+    use crate::asmbler::Asmbler;
+    use crate::layout::Image;
+
+    // (file, flags, input), every example that runs to its hlt
+    const EXAMPLES: &[(&str, &[&str], &[i32])] = &[
+        ("addressing.asml", &[], &[]),
+        ("conditional.asml", &[], &[]),
+        ("conditional.asml", &["-DDEBUG", "-DLEVEL=2"], &[]),
+        ("gcd.asml", &[], &[84, 36]),
+        ("hello.asml", &[], &[]),
+        ("input.asml", &[], &[]),
+        ("locals.asml", &[], &[]),
+        ("mm.asml", &[], &[5, 3, -7, 12, 0, 5]),
+        ("optimize.asml", &[], &[]),
+        ("pic.asml", &["--pic"], &[]),
+        ("procs.asml", &[], &[]),
+        ("structured.asml", &[], &[]),
+        ("stdlib/tests/array.asml", &[], &[]),
+        ("stdlib/tests/io.asml", &[], &[]),
+        ("stdlib/tests/math.asml", &[], &[]),
+        ("stdlib/tests/mem.asml", &[], &[]),
+    ];
+
+    const MAX_STEPS: usize = 1_000_000;
+
+    struct Run {
+        image: Image,
+        output: String,
+        memory: BTreeMap<u32, i32>,
+        // acc and idx after the hlt
+        registers: [i32; 2],
+        // the cells --optimize stopped storing to
+        dead: Vec<String>,
+    }
+
+    fn writec(simpletron: &mut Simpletron, _: i32) {
+        let value = simpletron.get_memory(simpletron.get_acc());
+        simpletron.print(&((value as u8) as char).to_string());
+    }
+
+    fn assert(simpletron: &mut Simpletron, operand: i32) {
+        let expected = if operand != 0 { operand } else { simpletron.get_ix() };
+        assert_eq!(simpletron.get_acc(), expected, "assert {} failed", operand);
+    }
+
+    fn run(file: &str, flags: &[&str], input: &[i32], optimize: bool) -> Run {
+        let contents = std::fs::read_to_string(file).unwrap_or_else(|e| panic!("{}: {}", file, e));
+        let mut flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+        flags.push(format!("--source={}", file));
+        if optimize {
+            flags.push("--optimize".to_string());
+        }
+        let result = Asmbler::assemble(contents, flags);
+        assert!(!result.has_errors(), "{} did not assemble: {:?}", file, result.errors);
+
+        let words = result.contents.lines().map(|line| line.parse().expect("a word")).collect();
+        let mut simpletron = Simpletron::new(words);
+        simpletron.add_extension_instruction(isa::opcode_of("WRITEC"), writec);
+        simpletron.add_extension_instruction(isa::opcode_of("ASSERT"), assert);
+        simpletron.set_input(input.to_vec());
+        simpletron.capture_output();
+        let mut steps = 0;
+        while !simpletron.is_halted() {
+            assert!(steps < MAX_STEPS, "{} did not halt", file);
+            simpletron.execute_step();
+            steps += 1;
+        }
+
+        let image = result.image.expect("assembled");
+        let memory = image.words.keys().map(|&addr| (addr, simpletron.get_memory(addr as i32))).collect();
+        let dead = result.optimizations.iter()
+            .filter_map(|(description, _, _)| description.strip_suffix(" is never read"))
+            .filter_map(|description| description.rsplit(", ").next())
+            .map(|name| name.to_string())
+            .collect();
+        let registers = [simpletron.get_acc(), simpletron.get_ix()];
+        Run { image, output: simpletron.output().to_string(), memory, registers, dead }
+    }
+
+    // (line, data, nth word of them) for every word, the same word in both images, a line can
+    // have code and data, .for puts a dw next to its loop, and only its code can be taken out
+    fn sources(image: &Image) -> BTreeMap<u32, (usize, bool, usize)> {
+        let mut counts: BTreeMap<(usize, bool), usize> = BTreeMap::new();
+        let mut sources = BTreeMap::new();
+        for (&addr, placed) in &image.words {
+            if let Some(line_num) = placed.line_num {
+                let count = counts.entry((line_num, placed.is_data)).or_default();
+                sources.insert(addr, (line_num, placed.is_data, *count));
+                *count += 1;
+            }
+        }
+        sources
+    }
+
+    #[test]
+    fn optimized_examples_behave_the_same() {
+        for &(file, flags, input) in EXAMPLES {
+            let original = run(file, flags, input, false);
+            let optimized = run(file, flags, input, true);
+            assert_eq!(original.output, optimized.output, "{} {:?} prints something else with --optimize", file, flags);
+
+            let (from, to) = (sources(&original.image), sources(&optimized.image));
+            let addr_of: BTreeMap<(usize, bool, usize), u32> = to.iter().map(|(&addr, &source)| (source, addr)).collect();
+            let dead: Vec<u32> = optimized.dead.iter().filter_map(|name| original.image.labels.get(name).copied()).collect();
+            // an address ends up wherever its word moved to
+            let same = |before: i32, after: i32| {
+                let same_place = u32::try_from(before).ok().and_then(|before| from.get(&before));
+                let moved_place = u32::try_from(after).ok().and_then(|after| to.get(&after));
+                before == after || (same_place.is_some() && same_place == moved_place)
+            };
+
+            for (register, (before, after)) in ["acc", "idx"].into_iter().zip(original.registers.into_iter().zip(optimized.registers)) {
+                assert!(same(before, after), "{} {:?}: {} halts as {} without --optimize and {} with it", file, flags, register, before, after);
+            }
+
+            for (&addr, placed) in &original.image.words {
+                if !placed.is_data || dead.contains(&addr) {
+                    continue;
+                }
+                let source = from[&addr];
+                let moved = *addr_of.get(&source).unwrap_or_else(|| panic!("{}: the data word at {} is gone", file, addr));
+                let (before, after) = (original.memory[&addr], optimized.memory[&moved]);
+                assert!(
+                    same(before, after),
+                    "{} {:?}: the data word from line {} ends as {} without --optimize and {} with it",
+                    file, flags, source.0 + 1, before, after
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::asmbler::AsmblerError;
+use crate::lexer::{tokenize, Token};
+
+// an operand as written
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    // acc, idx
+    Register(String),
+    Number(i32),
+    // a label or data symbol
+    Symbol(String),
+    Str(String),
+    // [term]
+    Memory(Box<Term>),
+    // idx+term inside [], idx-3 is idx plus -3
+    Offset(Box<Term>, Box<Term>),
+    // first..last
+    Range(Box<Term>, Box<Term>),
+    // name(term), the name is lower case
+    Call(String, Box<Term>),
+    // &label or &1f, the address of the label as a value
+    AddressOf(Box<Term>),
+    // 1b, 1f
+    NumericRef(u32, bool),
+    // left op right, op is one of == != < <= > >=, or = in .for idx = a to b
+    Compare(Box<Term>, &'static str, Box<Term>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    // mnemonics are lower case so MOV and mov are the same instruction
+    Instruction { mnemonic: String, operands: Vec<Term> },
+    // .org, .data ... also lower case, with the leading '.'
+    Directive { name: String, args: Vec<Term> },
+}
+
+// one source line, label: instr
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub line_num: usize,
+    pub labels: Vec<String>,
+    pub kind: Option<StatementKind>,
+}
+
+const REGISTERS: [&str; 2] = ["acc", "idx"];
+
+// a line that could not be parsed, with the labels in front of the mistake
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub error: AsmblerError,
+    pub line_num: usize,
+    pub labels: Vec<String>,
+}
+
+// parses every line on its own, a bad line does not stop the lines after it from being checked
+pub fn parse(contents: &str) -> Vec<Result<Statement, ParseError>> {
+    contents
+        .split('\n')
+        .enumerate()
+        .filter_map(|(line_num, line)| parse_line(line, line_num).transpose())
+        .collect()
+}
+
+fn parse_line(line: &str, line_num: usize) -> Result<Option<Statement>, ParseError> {
+    let tokens = match tokenize(line) {
+        Ok(tokens) => tokens,
+        Err((error, read)) => {
+            let (labels, _) = leading_labels(&read);
+            return Err(ParseError { error, line_num, labels });
+        }
+    };
+
+    let (labels, mut pos) = leading_labels(&tokens);
+    if let Some(Token::Colon) = tokens.get(pos + 1) {
+        return Err(ParseError { error: AsmblerError::SyntaxError, line_num, labels });
+    }
+
+    let name = match tokens.get(pos) {
+        None if labels.is_empty() => return Ok(None),
+        None => return Ok(Some(Statement { line_num, labels, kind: None })),
+        Some(Token::Ident(name)) => name.to_ascii_lowercase(),
+        Some(_) => return Err(ParseError { error: AsmblerError::SyntaxError, line_num, labels }),
+    };
+    pos += 1;
+
+    // operands are separated by spaces, a comma between them is allowed
+    let mut operands = Vec::new();
+    while pos < tokens.len() {
+        if !operands.is_empty() && tokens[pos] == Token::Comma {
+            pos += 1;
+        }
+        match parse_term(&tokens, &mut pos) {
+            Ok(term) => operands.push(term),
+            Err(error) => return Err(ParseError { error, line_num, labels }),
+        }
+    }
+
+    let kind = if name.starts_with('.') {
+        StatementKind::Directive { name, args: operands }
+    } else {
+        StatementKind::Instruction { mnemonic: name, operands }
+    };
+
+    Ok(Some(Statement { line_num, labels, kind: Some(kind) }))
+}
+
+// label: label: ... and the position of the first token after them
+fn leading_labels(tokens: &[Token]) -> (Vec<String>, usize) {
+    let mut labels = Vec::new();
+    let mut pos = 0;
+    while let (Some(token), Some(Token::Colon)) = (tokens.get(pos), tokens.get(pos + 1)) {
+        match token {
+            Token::Ident(name) if is_label_name(name) || is_local_label_name(name) => labels.push(name.clone()),
+            // 1: a numeric label
+            Token::Number(number) if *number >= 0 => labels.push(number.to_string()),
+            _ => break,
+        }
+        pos += 2;
+    }
+    (labels, pos)
+}
+
+fn parse_term(tokens: &[Token], pos: &mut usize) -> Result<Term, AsmblerError> {
+    let first = parse_single_term(tokens, pos)?;
+    match tokens.get(*pos) {
+        Some(Token::DotDot) => {
+            *pos += 1;
+            let last = parse_single_term(tokens, pos)?;
+            Ok(Term::Range(Box::new(first), Box::new(last)))
+        }
+        Some(Token::Compare(op)) => {
+            *pos += 1;
+            let right = parse_single_term(tokens, pos)?;
+            Ok(Term::Compare(Box::new(first), op, Box::new(right)))
+        }
+        _ => Ok(first),
+    }
+}
+
+fn parse_single_term(tokens: &[Token], pos: &mut usize) -> Result<Term, AsmblerError> {
+    let token = tokens.get(*pos).ok_or(AsmblerError::SyntaxError)?;
+    *pos += 1;
+
+    match token {
+        Token::Number(value) => Ok(Term::Number(*value)),
+        Token::Str(text) => Ok(Term::Str(text.clone())),
+        Token::Ident(name) if REGISTERS.contains(&name.to_ascii_lowercase().as_str()) => {
+            Ok(Term::Register(name.to_ascii_lowercase()))
+        }
+        Token::Ident(name) if tokens.get(*pos) == Some(&Token::LParen) => {
+            *pos += 1;
+            let arg = parse_term(tokens, pos)?;
+            match tokens.get(*pos) {
+                Some(Token::RParen) => {
+                    *pos += 1;
+                    Ok(Term::Call(name.to_ascii_lowercase(), Box::new(arg)))
+                }
+                _ => Err(AsmblerError::SyntaxError),
+            }
+        }
+        Token::Ident(name) if is_label_name(name) || is_local_label_name(name) => Ok(Term::Symbol(name.clone())),
+        Token::NumericRef(number, forward) => Ok(Term::NumericRef(*number, *forward)),
+        Token::Ampersand => match tokens.get(*pos) {
+            Some(Token::Ident(name)) if is_label_name(name) || is_local_label_name(name) => {
+                *pos += 1;
+                Ok(Term::AddressOf(Box::new(Term::Symbol(name.clone()))))
+            }
+            Some(Token::NumericRef(number, forward)) => {
+                *pos += 1;
+                Ok(Term::AddressOf(Box::new(Term::NumericRef(*number, *forward))))
+            }
+            _ => Err(AsmblerError::SyntaxError),
+        },
+        Token::LBracket => {
+            let mut inner = parse_term(tokens, pos)?;
+            // [idx+lit], [idx+label], [idx-lit]
+            let offset = match tokens.get(*pos) {
+                Some(Token::Plus) => {
+                    *pos += 1;
+                    Some(parse_single_term(tokens, pos)?)
+                }
+                Some(Token::Minus) => {
+                    *pos += 1;
+                    match parse_single_term(tokens, pos)? {
+                        Term::Number(value) => Some(Term::Number(-value)),
+                        _ => return Err(AsmblerError::SyntaxError),
+                    }
+                }
+                Some(Token::Number(value)) if *value < 0 => {
+                    *pos += 1;
+                    Some(Term::Number(*value))
+                }
+                _ => None,
+            };
+            if let Some(offset) = offset {
+                inner = Term::Offset(Box::new(inner), Box::new(offset));
+            }
+            match tokens.get(*pos) {
+                Some(Token::RBracket) => {
+                    *pos += 1;
+                    Ok(Term::Memory(Box::new(inner)))
+                }
+                _ => Err(AsmblerError::SyntaxError),
+            }
+        }
+        _ => Err(AsmblerError::SyntaxError),
+    }
+}
+
+pub fn is_label_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && !REGISTERS.contains(&text.to_ascii_lowercase().as_str())
+}
+
+// .loop, a label that belongs to the global label before it
+pub fn is_local_label_name(text: &str) -> bool {
+    text.strip_prefix('.').is_some_and(is_label_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asmbler::Asmbler;
+
+    fn statement(line: &str) -> Statement {
+        match parse(line).pop() {
+            Some(Ok(statement)) => statement,
+            other => panic!("{} did not parse: {:?}", line, other),
+        }
+    }
+
+    fn operands(line: &str) -> Vec<Term> {
+        match statement(line).kind {
+            Some(StatementKind::Instruction { operands, .. }) => operands,
+            other => panic!("{} is not an instruction: {:?}", line, other),
+        }
+    }
+
+    fn symbol(name: &str) -> Box<Term> {
+        Box::new(Term::Symbol(name.to_string()))
+    }
+
+    #[test]
+    fn mnemonics_and_registers_ignore_case() {
+        assert_eq!(statement("MOV ACC [IDX]"), statement("mov acc [idx]"));
+        assert_eq!(statement("Swap").kind, Some(StatementKind::Instruction { mnemonic: "swap".to_string(), operands: vec![] }));
+        assert_eq!(statement(".DATA").kind, Some(StatementKind::Directive { name: ".data".to_string(), args: vec![] }));
+        // labels keep their case
+        assert_eq!(operands("jmp Loop"), vec![Term::Symbol("Loop".to_string())]);
+    }
+
+    #[test]
+    fn labels_and_comments() {
+        let parsed = statement("a: b: mov acc 5 ; five");
+        assert_eq!(parsed.labels, vec!["a", "b"]);
+        assert_eq!(operands("mov acc, 5"), operands("mov acc 5"));
+        assert_eq!(statement("end:").kind, None);
+        assert_eq!(parse("   ; nothing but a comment\n\n"), vec![]);
+        assert_eq!(statement("1: swap").labels, vec!["1"]);
+    }
+
+    #[test]
+    fn addressing() {
+        assert_eq!(operands("mov acc [idx+table]"), vec![
+            Term::Register("acc".to_string()),
+            Term::Memory(Box::new(Term::Offset(Box::new(Term::Register("idx".to_string())), symbol("table")))),
+        ]);
+        let minus_three = Term::Memory(Box::new(Term::Offset(Box::new(Term::Register("idx".to_string())), Box::new(Term::Number(-3)))));
+        assert_eq!(operands("add acc [idx-3]")[1], minus_three);
+        assert_eq!(operands("add acc [idx - 3]")[1], minus_three);
+        assert_eq!(operands("mov acc [[ptr]]")[1], Term::Memory(Box::new(Term::Memory(symbol("ptr")))));
+        assert_eq!(operands("mov acc &label")[1], Term::AddressOf(symbol("label")));
+        assert_eq!(operands("hlt 0..pages(buffer)")[0], Term::Range(Box::new(Term::Number(0)), Box::new(Term::Call("pages".to_string(), symbol("buffer")))));
+    }
+
+    #[test]
+    fn errors_keep_the_labels_in_front() {
+        let errors: Vec<ParseError> = parse("first: second: mov acc $\nthird: mov [acc").into_iter().filter_map(Result::err).collect();
+        assert_eq!(errors, vec![
+            ParseError { error: AsmblerError::UnexpectedCharacter('$'), line_num: 0, labels: vec!["first".to_string(), "second".to_string()] },
+            ParseError { error: AsmblerError::SyntaxError, line_num: 1, labels: vec!["third".to_string()] },
+        ]);
+        assert_eq!(parse("mov acc -0").pop().map(|result| result.map_err(|e| e.error)), Some(Err(AsmblerError::NegativeZero)));
+    }
+
+    #[test]
+    fn a_bad_line_does_not_stop_the_rest() {
+        let result = Asmbler::assemble("mov acc $1\n    hlt\nmov acc 0x\nloop:\n    jmp loop\n    frob\n".to_string(), Vec::new());
+        let lines: Vec<usize> = result.errors.iter().map(|(_, _, line_num)| *line_num).collect();
+        assert_eq!(lines, vec![0, 2, 5]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+
+use crate::asmbler::AsmblerError;
+use crate::parser::{ParseError, Statement, StatementKind, Term};
+
+// proc name / endp, call name and ret, turned into plain statements before assembly
+//
+// calling convention:
+// - the argument goes in ACC and the result comes back in ACC
+// - call uses IX for the return address, so IX is not kept across a call
+// - anything else is passed in memory
+// - every proc has one return slot, name.__ret in the data section, so a proc can not call itself
+//   or be re-entered through a proc it calls
+//
+// call name
+//     swap                ; keeps the argument in IX
+//     mov acc &return
+//     mov [name.__ret] acc
+//     swap                ; argument back in ACC, IX = return address
+//     jmp name
+// return:
+//
+// ret
+//     jmp [name.__ret]
+//
+// labels defined between proc and endp belong to the proc, loop: in proc f is f.loop,
+// so two procs can both use loop, code outside the proc can reach it as f.loop
+//
+// .global f on a proc exports its return slot too, and .extern f lets call f reach a proc
+// in another object file
+
+const RETURN_SLOT: &str = "__ret";
+
+pub fn expand(statements: Vec<Result<Statement, ParseError>>) -> Vec<Result<Statement, ParseError>> {
+    let procs = proc_names(&statements);
+    // the procs whose return slot is exported or imported with them
+    let slots: HashSet<String> = procs.iter().filter(|name| is_called(&statements, name) || !is_extern(&statements, name)).cloned().collect();
+    let mut out = Vec::new();
+
+    // (name, line of the proc, labels local to it)
+    let mut current: Option<(String, usize, HashSet<String>)> = None;
+    let mut section = ".code";
+    let mut calls = 0;
+
+    for (index, statement) in statements.iter().enumerate() {
+        let statement = match statement {
+            Ok(statement) => statement,
+            Err(e) => {
+                let labels = match &current {
+                    Some((name, _, locals)) => e.labels.iter().map(|label| scoped(name, locals, label)).collect(),
+                    None => e.labels.clone(),
+                };
+                out.push(Err(ParseError { error: e.error.clone(), line_num: e.line_num, labels }));
+                continue;
+            }
+        };
+        let line_num = statement.line_num;
+        let error = |error: AsmblerError| Err(ParseError { error, line_num, labels: Vec::new() });
+
+        let statement = match &current {
+            Some((name, _, locals)) => rename(statement, name, locals),
+            None => statement.clone(),
+        };
+        let (mnemonic, operands) = match &statement.kind {
+            Some(StatementKind::Instruction { mnemonic, operands }) => (mnemonic.as_str(), operands.as_slice()),
+            Some(StatementKind::Directive { name, args }) => {
+                if name == ".code" || name == ".data" {
+                    section = if name == ".code" { ".code" } else { ".data" };
+                }
+                let slot = match args.as_slice() {
+                    [Term::Symbol(proc_name)] if (name == ".global" || name == ".extern") && slots.contains(proc_name) => {
+                        Some(Statement {
+                            line_num,
+                            labels: Vec::new(),
+                            kind: Some(StatementKind::Directive {
+                                name: name.clone(),
+                                args: vec![Term::Symbol(format!("{}.{}", proc_name, RETURN_SLOT))],
+                            }),
+                        })
+                    }
+                    _ => None,
+                };
+                out.push(Ok(statement));
+                out.extend(slot.map(Ok));
+                continue;
+            }
+            None => {
+                out.push(Ok(statement));
+                continue;
+            }
+        };
+
+        match (mnemonic, operands) {
+            ("proc", [Term::Symbol(name)]) => {
+                if current.is_some() {
+                    out.push(error(AsmblerError::NestedProcedure));
+                    continue;
+                }
+                let mut labels = statement.labels.clone();
+                labels.push(name.clone());
+                out.push(Ok(Statement { line_num, labels, kind: None }));
+                current = Some((name.clone(), line_num, local_labels(&statements[index + 1..])));
+            }
+            ("endp", []) => match current.take() {
+                Some((name, _, _)) => {
+                    label_only(&mut out, &statement);
+                    out.push(Ok(directive(line_num, ".data")));
+                    out.push(Ok(Statement {
+                        line_num,
+                        labels: vec![format!("{}.{}", name, RETURN_SLOT)],
+                        kind: Some(instruction("dw", vec![Term::Number(0)])),
+                    }));
+                    out.push(Ok(directive(line_num, section)));
+                }
+                None => out.push(error(AsmblerError::NotInProcedure)),
+            },
+            ("call", [Term::Symbol(name)]) => {
+                if !procs.contains(name) {
+                    out.push(error(AsmblerError::UndefinedProcedure(name.clone())));
+                    continue;
+                }
+                let return_label = format!("__call{}", calls);
+                calls += 1;
+                let slot = Term::Memory(Box::new(Term::Symbol(format!("{}.{}", name, RETURN_SLOT))));
+                let acc = || Term::Register("acc".to_string());
+
+                let mut sequence = vec![
+                    instruction("swap", vec![]),
+                    instruction("mov", vec![acc(), Term::AddressOf(Box::new(Term::Symbol(return_label.clone())))]),
+                    instruction("mov", vec![slot, acc()]),
+                    instruction("swap", vec![]),
+                    instruction("jmp", vec![Term::Symbol(name.clone())]),
+                ].into_iter();
+                out.push(Ok(Statement { line_num, labels: statement.labels.clone(), kind: sequence.next() }));
+                out.extend(sequence.map(|kind| Ok(Statement { line_num, labels: Vec::new(), kind: Some(kind) })));
+                out.push(Ok(Statement { line_num, labels: vec![return_label], kind: None }));
+            }
+            ("ret", []) => match &current {
+                Some((name, _, _)) => {
+                    let slot = Term::Memory(Box::new(Term::Symbol(format!("{}.{}", name, RETURN_SLOT))));
+                    out.push(Ok(Statement { line_num, labels: statement.labels.clone(), kind: Some(instruction("jmp", vec![slot])) }));
+                }
+                None => out.push(error(AsmblerError::NotInProcedure)),
+            },
+            ("proc" | "endp" | "call" | "ret", _) => out.push(error(AsmblerError::SyntaxError)),
+            _ => out.push(Ok(statement)),
+        }
+    }
+
+    if let Some((_, line_num, _)) = current {
+        out.push(Err(ParseError { error: AsmblerError::UnterminatedProcedure, line_num, labels: Vec::new() }));
+    }
+
+    out
+}
+
+// the procs defined here and the .extern names, which can only be procs if they are called
+fn proc_names(statements: &[Result<Statement, ParseError>]) -> HashSet<String> {
+    statements.iter()
+        .filter_map(|statement| match statement {
+            Ok(Statement { kind: Some(StatementKind::Instruction { mnemonic, operands }), .. }) if mnemonic == "proc" => {
+                match operands.as_slice() {
+                    [Term::Symbol(name)] => Some(name.clone()),
+                    _ => None,
+                }
+            }
+            Ok(Statement { kind: Some(StatementKind::Directive { name, args }), .. }) if name == ".extern" => match args.as_slice() {
+                [Term::Symbol(name)] => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_called(statements: &[Result<Statement, ParseError>], proc_name: &str) -> bool {
+    statements.iter().any(|statement| matches!(statement,
+        Ok(Statement { kind: Some(StatementKind::Instruction { mnemonic, operands }), .. })
+            if mnemonic == "call" && matches!(operands.as_slice(), [Term::Symbol(name)] if name == proc_name)))
+}
+
+fn is_extern(statements: &[Result<Statement, ParseError>], label: &str) -> bool {
+    statements.iter().any(|statement| matches!(statement,
+        Ok(Statement { kind: Some(StatementKind::Directive { name, args }), .. })
+            if name == ".extern" && matches!(args.as_slice(), [Term::Symbol(arg)] if arg == label)))
+}
+
+// every label defined from here to the next endp
+fn local_labels(statements: &[Result<Statement, ParseError>]) -> HashSet<String> {
+    let mut locals = HashSet::new();
+    for statement in statements {
+        let (labels, kind) = match statement {
+            Ok(statement) => (&statement.labels, &statement.kind),
+            Err(e) => {
+                locals.extend(e.labels.iter().cloned());
+                continue;
+            }
+        };
+        locals.extend(labels.iter().cloned());
+        match kind {
+            Some(StatementKind::Instruction { mnemonic, .. }) if mnemonic == "endp" => break,
+            // dw name value
+            Some(StatementKind::Instruction { mnemonic, operands }) if mnemonic == "dw" => {
+                if let [Term::Symbol(name), _] = operands.as_slice() {
+                    locals.insert(name.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    locals
+}
+
+fn scoped(proc_name: &str, locals: &HashSet<String>, label: &str) -> String {
+    // .local labels directly under the proc are already named after it
+    if locals.contains(label) && !label.starts_with(&format!("{}.", proc_name)) {
+        format!("{}.{}", proc_name, label)
+    } else {
+        label.to_string()
+    }
+}
+
+fn rename(statement: &Statement, proc_name: &str, locals: &HashSet<String>) -> Statement {
+    let rename_terms = |terms: &Vec<Term>| terms.iter().map(|term| rename_term(term, proc_name, locals)).collect();
+    Statement {
+        line_num: statement.line_num,
+        labels: statement.labels.iter().map(|label| scoped(proc_name, locals, label)).collect(),
+        kind: statement.kind.as_ref().map(|kind| match kind {
+            StatementKind::Instruction { mnemonic, operands } => {
+                StatementKind::Instruction { mnemonic: mnemonic.clone(), operands: rename_terms(operands) }
+            }
+            StatementKind::Directive { name, args } => StatementKind::Directive { name: name.clone(), args: rename_terms(args) },
+        }),
+    }
+}
+
+fn rename_term(term: &Term, proc_name: &str, locals: &HashSet<String>) -> Term {
+    let inner = |term: &Term| Box::new(rename_term(term, proc_name, locals));
+    match term {
+        Term::Symbol(name) => Term::Symbol(scoped(proc_name, locals, name)),
+        Term::AddressOf(term) => Term::AddressOf(inner(term)),
+        Term::Memory(term) => Term::Memory(inner(term)),
+        Term::Offset(base, offset) => Term::Offset(inner(base), inner(offset)),
+        Term::Range(first, last) => Term::Range(inner(first), inner(last)),
+        Term::Call(name, arg) => Term::Call(name.clone(), inner(arg)),
+        Term::Compare(left, op, right) => Term::Compare(inner(left), op, inner(right)),
+        Term::Register(_) | Term::Number(_) | Term::Str(_) | Term::NumericRef(..) => term.clone(),
+    }
+}
+
+// the labels of a statement that expands to nothing
+fn label_only(out: &mut Vec<Result<Statement, ParseError>>, statement: &Statement) {
+    if !statement.labels.is_empty() {
+        out.push(Ok(Statement { line_num: statement.line_num, labels: statement.labels.clone(), kind: None }));
+    }
+}
+
+fn instruction(mnemonic: &str, operands: Vec<Term>) -> StatementKind {
+    StatementKind::Instruction { mnemonic: mnemonic.to_string(), operands }
+}
+
+fn directive(line_num: usize, name: &str) -> Statement {
+    Statement { line_num, labels: Vec::new(), kind: Some(StatementKind::Directive { name: name.to_string(), args: Vec::new() }) }
+}
@@ -0,0 +1,285 @@
+use crate::asmbler::AsmblerError;
+use crate::parser::{ParseError, Statement, StatementKind, Term};
+
+// .if / .else / .endif, .while / .endw, .repeat / .until and .for / .endfor on acc and idx,
+// turned into jlz, jez and jmp with labels of their own before procs are expanded
+//
+// a condition compares acc with 0, the only tests the branches have:
+// .if acc < 0        .while acc != 0        .until acc >= 0
+//
+// .if acc > 0                  jlz __if0_else
+//     ...                      jez __if0_else
+// .else                        ...
+//     ...                      jmp __if0_end
+// .endif                   __if0_else:
+//                              ...
+//                          __if0_end:
+//
+// .while tests at the top and jumps back after the body, .repeat runs the body first and goes
+// back to it until the condition holds
+//
+// .for idx = 1 to 10 counts idx up to and including the last value, acc is kept through the
+// test, the last value is a word in the data section
+//
+// the labels are __if0, __while1 ... so inside a proc they belong to it like any other label
+
+struct Block {
+    kind: BlockKind,
+    // the generated labels start with this, __if0
+    name: String,
+    line_num: usize,
+}
+
+enum BlockKind {
+    If { seen_else: bool },
+    While,
+    Repeat,
+    For,
+}
+
+pub fn expand(statements: Vec<Result<Statement, ParseError>>) -> Vec<Result<Statement, ParseError>> {
+    let mut stack: Vec<Block> = Vec::new();
+    let mut out = Vec::new();
+    let mut section = ".code";
+    let mut blocks = 0;
+
+    for statement in statements {
+        let statement = match statement {
+            Ok(statement) => statement,
+            Err(e) => {
+                out.push(Err(e));
+                continue;
+            }
+        };
+        let (name, args) = match &statement.kind {
+            Some(StatementKind::Directive { name, args })
+                if matches!(name.as_str(), ".if" | ".else" | ".endif" | ".while" | ".endw" | ".repeat" | ".until" | ".for" | ".endfor") =>
+            {
+                (name.as_str(), args.as_slice())
+            }
+            Some(StatementKind::Directive { name, .. }) => {
+                if name == ".code" || name == ".data" {
+                    section = if name == ".code" { ".code" } else { ".data" };
+                }
+                out.push(Ok(statement));
+                continue;
+            }
+            _ => {
+                out.push(Ok(statement));
+                continue;
+            }
+        };
+
+        let line_num = statement.line_num;
+        let mut emit = Emit { out: Vec::new(), line_num, labels: statement.labels.clone() };
+
+        let result = match name {
+            ".if" | ".while" | ".repeat" | ".for" => {
+                let block_name = format!("__{}{}", &name[1..], blocks);
+                blocks += 1;
+                let opened = match name {
+                    ".if" => condition(args).map(|op| {
+                        emit.jump_if(negate(op), &format!("{}_else", block_name), &block_name);
+                        BlockKind::If { seen_else: false }
+                    }),
+                    ".while" => condition(args).map(|op| {
+                        emit.label(&block_name);
+                        emit.jump_if(negate(op), &format!("{}_end", block_name), &block_name);
+                        BlockKind::While
+                    }),
+                    ".repeat" if args.is_empty() => {
+                        emit.label(&block_name);
+                        Ok(BlockKind::Repeat)
+                    }
+                    ".for" => for_range(args).map(|(first, last)| {
+                        let last_label = format!("{}_last", block_name);
+                        emit.data_word(&last_label, last, section);
+                        emit.instr("swap", vec![]);
+                        emit.instr("mov", vec![acc(), Term::Number(first)]);
+                        emit.instr("swap", vec![]);
+                        emit.label(&block_name);
+                        // idx - last <= 0 goes into the body, acc is put back either way
+                        let last_word = || Term::Memory(Box::new(Term::Symbol(last_label.clone())));
+                        let body = format!("{}_body", block_name);
+                        emit.instr("swap", vec![]);
+                        emit.instr("sub", vec![acc(), last_word()]);
+                        emit.instr("jlz", vec![Term::Symbol(body.clone())]);
+                        emit.instr("jez", vec![Term::Symbol(body.clone())]);
+                        emit.instr("add", vec![acc(), last_word()]);
+                        emit.instr("swap", vec![]);
+                        emit.instr("jmp", vec![Term::Symbol(format!("{}_end", block_name))]);
+                        emit.label(&body);
+                        emit.instr("add", vec![acc(), last_word()]);
+                        emit.instr("swap", vec![]);
+                        BlockKind::For
+                    }),
+                    _ => Err(AsmblerError::SyntaxError),
+                };
+                opened.map(|kind| stack.push(Block { kind, name: block_name, line_num }))
+            }
+            ".else" => match stack.last_mut() {
+                Some(Block { kind: BlockKind::If { seen_else }, name, .. }) if !*seen_else && args.is_empty() => {
+                    *seen_else = true;
+                    emit.instr("jmp", vec![Term::Symbol(format!("{}_end", name))]);
+                    emit.label(&format!("{}_else", name));
+                    Ok(())
+                }
+                Some(Block { kind: BlockKind::If { seen_else: false }, .. }) => Err(AsmblerError::SyntaxError),
+                _ => Err(AsmblerError::UnmatchedBlock),
+            },
+            _ => match (name, stack.last()) {
+                (_, Some(_)) if name != ".until" && !args.is_empty() => Err(AsmblerError::SyntaxError),
+                (".endif", Some(Block { kind: BlockKind::If { seen_else }, name: block_name, .. })) => {
+                    // without an .else the false branch lands on the end
+                    let end = if *seen_else { "end" } else { "else" };
+                    emit.label(&format!("{}_{}", block_name, end));
+                    stack.pop();
+                    Ok(())
+                }
+                (".endw" | ".endfor", Some(Block { kind: kind @ (BlockKind::While | BlockKind::For), name: block_name, .. }))
+                    if (name == ".endw") == matches!(kind, BlockKind::While) =>
+                {
+                    if matches!(kind, BlockKind::For) {
+                        emit.instr("inc", vec![]);
+                    }
+                    emit.instr("jmp", vec![Term::Symbol(block_name.clone())]);
+                    emit.label(&format!("{}_end", block_name));
+                    stack.pop();
+                    Ok(())
+                }
+                (".until", Some(Block { kind: BlockKind::Repeat, name: block_name, .. })) => {
+                    let block_name = block_name.clone();
+                    condition(args).map(|op| {
+                        emit.jump_if(negate(op), &block_name, &block_name);
+                        stack.pop();
+                    })
+                }
+                _ => Err(AsmblerError::UnmatchedBlock),
+            },
+        };
+
+        match result {
+            Ok(()) => {
+                // a block that adds no words still keeps the labels in front of it
+                if !emit.labels.is_empty() {
+                    emit.out.push(Statement { line_num, labels: emit.labels, kind: None });
+                }
+                out.extend(emit.out.into_iter().map(Ok));
+            }
+            Err(error) => out.push(Err(ParseError { error, line_num, labels: statement.labels })),
+        }
+    }
+
+    for block in stack {
+        out.push(Err(ParseError { error: AsmblerError::UnterminatedBlock, line_num: block.line_num, labels: Vec::new() }));
+    }
+
+    out
+}
+
+// acc op 0
+fn condition(args: &[Term]) -> Result<&'static str, AsmblerError> {
+    match args {
+        [Term::Compare(left, op, right)] if *op != "=" => match (left.as_ref(), right.as_ref()) {
+            (Term::Register(register), Term::Number(0)) if register == "acc" => Ok(op),
+            _ => Err(AsmblerError::InvalidOperand),
+        },
+        _ => Err(AsmblerError::SyntaxError),
+    }
+}
+
+// idx = first to last
+fn for_range(args: &[Term]) -> Result<(i32, i32), AsmblerError> {
+    match args {
+        [Term::Compare(counter, "=", first), Term::Symbol(to), last] if to.eq_ignore_ascii_case("to") => {
+            match (counter.as_ref(), first.as_ref(), last) {
+                (Term::Register(register), Term::Number(first), Term::Number(last)) if register == "idx" => Ok((*first, *last)),
+                _ => Err(AsmblerError::InvalidOperand),
+            }
+        }
+        _ => Err(AsmblerError::SyntaxError),
+    }
+}
+
+fn negate(op: &str) -> &'static str {
+    match op {
+        "<" => ">=",
+        ">=" => "<",
+        "==" => "!=",
+        "!=" => "==",
+        "<=" => ">",
+        _ => "<=",
+    }
+}
+
+fn acc() -> Term {
+    Term::Register("acc".to_string())
+}
+
+struct Emit {
+    out: Vec<Statement>,
+    line_num: usize,
+    // the labels of the directive go on the first statement
+    labels: Vec<String>,
+}
+
+impl Emit {
+    fn push(&mut self, labels: Vec<String>, kind: Option<StatementKind>) {
+        let mut all = std::mem::take(&mut self.labels);
+        all.extend(labels);
+        self.out.push(Statement { line_num: self.line_num, labels: all, kind });
+    }
+
+    fn instr(&mut self, mnemonic: &str, operands: Vec<Term>) {
+        self.push(Vec::new(), Some(StatementKind::Instruction { mnemonic: mnemonic.to_string(), operands }));
+    }
+
+    fn label(&mut self, name: &str) {
+        self.push(vec![name.to_string()], None);
+    }
+
+    fn directive(&mut self, name: &str) {
+        self.out.push(Statement { line_num: self.line_num, labels: Vec::new(), kind: Some(StatementKind::Directive { name: name.to_string(), args: Vec::new() }) });
+    }
+
+    fn data_word(&mut self, label: &str, value: i32, section: &str) {
+        self.directive(".data");
+        self.out.push(Statement {
+            line_num: self.line_num,
+            labels: vec![label.to_string()],
+            kind: Some(StatementKind::Instruction { mnemonic: "dw".to_string(), operands: vec![Term::Number(value)] }),
+        });
+        self.directive(section);
+    }
+
+    // goes to target when acc op 0, falls through otherwise, name_skip is used to step over the jmp
+    fn jump_if(&mut self, op: &str, target: &str, name: &str) {
+        let target = || vec![Term::Symbol(target.to_string())];
+        let skip = format!("{}_skip", name);
+        let to_skip = || vec![Term::Symbol(skip.clone())];
+        match op {
+            "<" => self.instr("jlz", target()),
+            "==" => self.instr("jez", target()),
+            "<=" => {
+                self.instr("jlz", target());
+                self.instr("jez", target());
+            }
+            ">=" => {
+                self.instr("jlz", to_skip());
+                self.instr("jmp", target());
+                self.label(&skip);
+            }
+            "!=" => {
+                self.instr("jez", to_skip());
+                self.instr("jmp", target());
+                self.label(&skip);
+            }
+            _ => {
+                self.instr("jlz", to_skip());
+                self.instr("jez", to_skip());
+                self.instr("jmp", target());
+                self.label(&skip);
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::include::Sources;
+use crate::layout::Image;
+
+// the debug file read by the simulator, one record per line
+//   line <addr> <line> <enclosing label or -> <file>
+//   label <name> <addr>
+//   data <name> <addr>
+pub fn symbol_file(image: &Image, sources: &Sources) -> String {
+    let mut out = String::from("; simpletron symbols\n");
+
+    let mut code_labels: Vec<(u32, &str)> = image.labels.iter()
+        .filter(|&(_, addr)| !image.words.get(addr).is_some_and(|placed| placed.is_data))
+        .map(|(name, &addr)| (addr, name.as_str()))
+        .collect();
+    code_labels.sort();
+
+    for (&addr, placed) in &image.words {
+        let Some(line_num) = placed.line_num else { continue };
+        // code is inside the last label before it, data only belongs to a label on the word itself
+        let scope = if placed.is_data {
+            image.labels.iter().find(|&(_, &label_addr)| label_addr == addr).map_or("-", |(name, _)| name.as_str())
+        } else {
+            code_labels.iter()
+                .rev()
+                .find(|&&(label_addr, _)| label_addr <= addr)
+                .map_or("-", |&(_, name)| name)
+        };
+        let (file, line) = sources.location(line_num);
+        out.push_str(&format!("line {:04} {} {} {}\n", addr, line + 1, scope, file));
+    }
+
+    for (name, &addr) in &image.labels {
+        let kind = if image.words.get(&addr).is_some_and(|placed| placed.is_data) { "data" } else { "label" };
+        out.push_str(&format!("{} {} {:04}\n", kind, name, addr));
+    }
+
+    out
+}
@@ -0,0 +1,4 @@
+// prints the markdown reference for the ISA, ISA.md is this output
+fn main() {
+    print!("{}", isa::reference());
+}
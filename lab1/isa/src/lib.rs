@@ -0,0 +1,241 @@
+// The Simpletron instruction set, shared by the simulator, the assembler and the disassembler.
+// Everything that knows an opcode number should get it from INSTRUCTIONS.
+
+// A word is a sign followed by six decimal digits.
+// Instructions use the top two digits for the opcode and the bottom four for the operand,
+// the sign of the word belongs to the operand.
+pub const MAX_WORD: i32 = 999999;
+pub const MIN_WORD: i32 = -999999;
+
+pub const MAX_OPCODE: u32 = 99;
+
+pub const MAX_OPERAND: i32 = 9999;
+pub const MIN_OPERAND: i32 = -9999;
+
+pub const OPCODE_SCALE: i32 = 10000;
+
+pub const MEMORY_PAGES: usize = 100;
+pub const WORDS_PER_PAGE: usize = 100;
+pub const MEMORY_SIZE: usize = MEMORY_PAGES * WORDS_PER_PAGE;
+
+// the pieces an instruction is written with in asml
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arg {
+    // acc
+    Acc,
+    // idx
+    Idx,
+    // [idx]
+    AtIdx,
+    // [lit] or [label], the address is the operand
+    Mem,
+    // lit, the value is the operand
+    Imm,
+    // label, the address to branch to is the operand
+    Target,
+    // first..last, a range of pages packed as first * 100 + last
+    Pages,
+    // [idx+lit] or [idx+label], the displacement is the operand
+    IdxOffset,
+    // [[lit]] or [[label]], the address of the pointer is the operand
+    Indirect,
+    // label, the distance from the branch to the label is the operand
+    RelTarget,
+}
+
+impl Arg {
+    // true when this piece is what ends up in the operand
+    pub fn is_operand(&self) -> bool {
+        matches!(self, Arg::Mem | Arg::Imm | Arg::Target | Arg::Pages | Arg::IdxOffset | Arg::Indirect | Arg::RelTarget)
+    }
+
+    pub fn syntax(&self) -> &'static str {
+        match self {
+            Arg::Acc => "acc",
+            Arg::Idx => "idx",
+            Arg::AtIdx => "[idx]",
+            Arg::Mem => "[lit]",
+            Arg::Imm => "lit",
+            Arg::Target => "label",
+            Arg::Pages => "first..last",
+            Arg::IdxOffset => "[idx+lit]",
+            Arg::Indirect => "[[lit]]",
+            Arg::RelTarget => "label",
+        }
+    }
+}
+
+// what an instruction does to the instruction pointer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    // carries on with the next word
+    Next,
+    // always goes to the operand
+    Jump,
+    // goes to the operand or carries on
+    Branch,
+    // stops the machine
+    Stop,
+    // goes to an address only known at run time
+    Indirect,
+}
+
+// what an instruction does with MEM[operand]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+pub struct Instruction {
+    pub opcode: u32,
+    // the simulator's name
+    pub name: &'static str,
+    // the assembler's name, several instructions can share one
+    pub mnemonic: &'static str,
+    // every way the instruction can be written after the mnemonic, an instruction without
+    // an operand piece always has an operand of 0
+    pub forms: &'static [&'static [Arg]],
+    // not built into the machine, the host registers it with add_extension_instruction
+    pub extension: bool,
+    pub flow: Flow,
+    pub semantics: &'static str,
+}
+
+impl Instruction {
+    // the form used to write a word back out, the one that can carry the operand
+    pub fn form_for(&self, operand: i32) -> Option<&'static [Arg]> {
+        self.forms.iter().copied().find(|form| {
+            match form.iter().find(|arg| arg.is_operand()) {
+                Some(Arg::Imm | Arg::IdxOffset | Arg::RelTarget) => true,
+                Some(_) => operand >= 0,
+                None => operand == 0,
+            }
+        })
+    }
+
+    // for tools that follow memory, [[lit]] and jmp [lit] read the pointer at the operand
+    pub fn operand_access(&self) -> Option<Access> {
+        match self.name {
+            "READ" | "STORE" => Some(Access::Write),
+            "WRITE" | "LOAD" | "LOADX" | "ADD" | "SUBTRACT" | "DIVIDE" | "MULTIPLY" | "LOADIND" | "STOREIND" | "BRANCHIND" => Some(Access::Read),
+            _ => None,
+        }
+    }
+
+    // stores to an address only known at run time, through IX or a pointer
+    pub fn stores_indirectly(&self) -> bool {
+        matches!(self.name, "STOREIDX" | "STOREOFF" | "STOREIND")
+    }
+
+    // where a branch at addr goes, the operand itself unless it is relative to the branch
+    pub fn target(&self, addr: i32, operand: i32) -> i32 {
+        if self.forms.iter().any(|form| form.contains(&Arg::RelTarget)) {
+            addr + operand
+        } else {
+            operand
+        }
+    }
+}
+
+pub const INSTRUCTIONS: &[Instruction] = &[
+    Instruction { opcode: 1,  name: "ASSERT",    mnemonic: "assert", forms: &[&[], &[Arg::Imm]],        extension: true,  flow: Flow::Next,   semantics: "operand 0: stop unless ACC == IX, otherwise stop unless ACC == operand" },
+    Instruction { opcode: 10, name: "READ",      mnemonic: "read",   forms: &[&[Arg::Mem]],             extension: false, flow: Flow::Next,   semantics: "MEM[operand] = a word read from the terminal" },
+    Instruction { opcode: 11, name: "WRITE",     mnemonic: "write",  forms: &[&[Arg::Mem]],             extension: false, flow: Flow::Next,   semantics: "write MEM[operand] to the terminal" },
+    Instruction { opcode: 12, name: "WRITEC",    mnemonic: "writec", forms: &[&[]],                     extension: true,  flow: Flow::Next,   semantics: "write MEM[ACC] to the terminal as an ascii character" },
+    Instruction { opcode: 20, name: "LOAD",      mnemonic: "mov",    forms: &[&[Arg::Acc, Arg::Mem]],   extension: false, flow: Flow::Next,   semantics: "ACC = MEM[operand]" },
+    Instruction { opcode: 21, name: "LOADIM",    mnemonic: "mov",    forms: &[&[Arg::Acc, Arg::Imm]],   extension: false, flow: Flow::Next,   semantics: "ACC = operand" },
+    Instruction { opcode: 22, name: "LOADX",     mnemonic: "mov",    forms: &[&[Arg::Idx, Arg::Mem]],   extension: false, flow: Flow::Next,   semantics: "IX = MEM[operand]" },
+    Instruction { opcode: 23, name: "LOADIDX",   mnemonic: "mov",    forms: &[&[Arg::Acc, Arg::AtIdx]], extension: false, flow: Flow::Next,   semantics: "ACC = MEM[IX]" },
+    Instruction { opcode: 25, name: "STORE",     mnemonic: "mov",    forms: &[&[Arg::Mem, Arg::Acc]],   extension: false, flow: Flow::Next,   semantics: "MEM[operand] = ACC" },
+    Instruction { opcode: 26, name: "STOREIDX",  mnemonic: "mov",    forms: &[&[Arg::AtIdx, Arg::Acc]], extension: false, flow: Flow::Next,   semantics: "MEM[IX] = ACC" },
+    Instruction { opcode: 30, name: "ADD",       mnemonic: "add",    forms: &[&[Arg::Acc, Arg::Mem]],   extension: false, flow: Flow::Next,   semantics: "ACC += MEM[operand]" },
+    Instruction { opcode: 31, name: "ADDX",      mnemonic: "add",    forms: &[&[Arg::Acc, Arg::AtIdx]], extension: false, flow: Flow::Next,   semantics: "ACC += MEM[IX]" },
+    Instruction { opcode: 32, name: "SUBTRACT",  mnemonic: "sub",    forms: &[&[Arg::Acc, Arg::Mem]],   extension: false, flow: Flow::Next,   semantics: "ACC -= MEM[operand]" },
+    Instruction { opcode: 33, name: "SUBTRACTX", mnemonic: "sub",    forms: &[&[Arg::Acc, Arg::AtIdx]], extension: false, flow: Flow::Next,   semantics: "ACC -= MEM[IX]" },
+    Instruction { opcode: 34, name: "DIVIDE",    mnemonic: "div",    forms: &[&[Arg::Acc, Arg::Mem]],   extension: false, flow: Flow::Next,   semantics: "ACC /= MEM[operand], the remainder is lost" },
+    Instruction { opcode: 35, name: "DIVIDEX",   mnemonic: "div",    forms: &[&[Arg::Acc, Arg::AtIdx]], extension: false, flow: Flow::Next,   semantics: "ACC /= MEM[IX], the remainder is lost" },
+    Instruction { opcode: 36, name: "MULTIPLY",  mnemonic: "mul",    forms: &[&[Arg::Acc, Arg::Mem]],   extension: false, flow: Flow::Next,   semantics: "ACC *= MEM[operand]" },
+    Instruction { opcode: 37, name: "MULTIPLYX", mnemonic: "mul",    forms: &[&[Arg::Acc, Arg::AtIdx]], extension: false, flow: Flow::Next,   semantics: "ACC *= MEM[IX]" },
+    Instruction { opcode: 38, name: "INC",       mnemonic: "inc",    forms: &[&[]],                     extension: false, flow: Flow::Next,   semantics: "IX += 1" },
+    Instruction { opcode: 39, name: "DEC",       mnemonic: "dec",    forms: &[&[]],                     extension: false, flow: Flow::Next,   semantics: "IX -= 1" },
+    Instruction { opcode: 40, name: "BRANCH",    mnemonic: "jmp",    forms: &[&[Arg::Target]],          extension: false, flow: Flow::Jump,   semantics: "go to operand" },
+    Instruction { opcode: 41, name: "BRANCHNEG", mnemonic: "jlz",    forms: &[&[Arg::Target]],          extension: false, flow: Flow::Branch, semantics: "go to operand if ACC < 0" },
+    Instruction { opcode: 42, name: "BRANCHZERO", mnemonic: "jez",    forms: &[&[Arg::Target]],          extension: false, flow: Flow::Branch, semantics: "go to operand if ACC == 0" },
+    Instruction { opcode: 43, name: "SWAP",      mnemonic: "swap",   forms: &[&[]],                     extension: false, flow: Flow::Next,   semantics: "swap ACC and IX" },
+    Instruction { opcode: 45, name: "HALT",      mnemonic: "hlt",    forms: &[&[], &[Arg::Pages]],      extension: false, flow: Flow::Stop,   semantics: "dump the registers and pages operand / 100 to operand % 100, then stop" },
+    Instruction { opcode: 50, name: "LOADOFF",   mnemonic: "mov",    forms: &[&[Arg::Acc, Arg::IdxOffset]], extension: false, flow: Flow::Next,   semantics: "ACC = MEM[IX + operand]" },
+    Instruction { opcode: 51, name: "STOREOFF",  mnemonic: "mov",    forms: &[&[Arg::IdxOffset, Arg::Acc]], extension: false, flow: Flow::Next,   semantics: "MEM[IX + operand] = ACC" },
+    Instruction { opcode: 52, name: "ADDOFF",    mnemonic: "add",    forms: &[&[Arg::Acc, Arg::IdxOffset]], extension: false, flow: Flow::Next,   semantics: "ACC += MEM[IX + operand]" },
+    Instruction { opcode: 53, name: "SUBTRACTOFF", mnemonic: "sub",  forms: &[&[Arg::Acc, Arg::IdxOffset]], extension: false, flow: Flow::Next,   semantics: "ACC -= MEM[IX + operand]" },
+    Instruction { opcode: 54, name: "DIVIDEOFF", mnemonic: "div",    forms: &[&[Arg::Acc, Arg::IdxOffset]], extension: false, flow: Flow::Next,   semantics: "ACC /= MEM[IX + operand], the remainder is lost" },
+    Instruction { opcode: 55, name: "MULTIPLYOFF", mnemonic: "mul",  forms: &[&[Arg::Acc, Arg::IdxOffset]], extension: false, flow: Flow::Next,   semantics: "ACC *= MEM[IX + operand]" },
+    Instruction { opcode: 56, name: "LOADIND",   mnemonic: "mov",    forms: &[&[Arg::Acc, Arg::Indirect]],  extension: false, flow: Flow::Next,   semantics: "ACC = MEM[MEM[operand]]" },
+    Instruction { opcode: 57, name: "STOREIND",  mnemonic: "mov",    forms: &[&[Arg::Indirect, Arg::Acc]],  extension: false, flow: Flow::Next,   semantics: "MEM[MEM[operand]] = ACC" },
+    Instruction { opcode: 58, name: "BRANCHX",   mnemonic: "jmp",    forms: &[&[Arg::Idx]],             extension: false, flow: Flow::Indirect, semantics: "go to IX" },
+    Instruction { opcode: 59, name: "BRANCHIND", mnemonic: "jmp",    forms: &[&[Arg::Mem]],             extension: false, flow: Flow::Indirect, semantics: "go to MEM[operand]" },
+    Instruction { opcode: 60, name: "BRANCHREL", mnemonic: "jmpr",   forms: &[&[Arg::RelTarget]],       extension: false, flow: Flow::Jump,   semantics: "go to the address of this instruction + operand" },
+    Instruction { opcode: 61, name: "BRANCHNEGREL", mnemonic: "jlzr", forms: &[&[Arg::RelTarget]],      extension: false, flow: Flow::Branch, semantics: "go to the address of this instruction + operand if ACC < 0" },
+    Instruction { opcode: 62, name: "BRANCHZEROREL", mnemonic: "jezr", forms: &[&[Arg::RelTarget]],     extension: false, flow: Flow::Branch, semantics: "go to the address of this instruction + operand if ACC == 0" },
+];
+
+pub fn by_opcode(opcode: u32) -> Option<&'static Instruction> {
+    INSTRUCTIONS.iter().find(|instr| instr.opcode == opcode)
+}
+
+pub fn by_name(name: &str) -> Option<&'static Instruction> {
+    INSTRUCTIONS.iter().find(|instr| instr.name == name)
+}
+
+// for instructions the tools put in on their own, which have to exist
+pub fn opcode_of(name: &str) -> u32 {
+    by_name(name).unwrap_or_else(|| panic!("{} is not in the ISA", name)).opcode
+}
+
+pub fn by_mnemonic(mnemonic: &str) -> impl Iterator<Item = &'static Instruction> + '_ {
+    INSTRUCTIONS.iter().filter(move |instr| instr.mnemonic == mnemonic)
+}
+
+// (opcode, operand) the way the machine reads a word
+pub fn decode(word: i32) -> (u32, i32) {
+    let opcode = (word / OPCODE_SCALE).unsigned_abs();
+    let operand = word % OPCODE_SCALE;
+    (opcode, operand)
+}
+
+// the inverse of decode, the caller checks the ranges
+pub fn encode(opcode: u32, operand: i32) -> i32 {
+    let magnitude = opcode as i32 * OPCODE_SCALE + operand.abs();
+    if operand < 0 { -magnitude } else { magnitude }
+}
+
+pub fn format_word(word: i32) -> String {
+    format!("{:+07}", word)
+}
+
+// markdown reference for every instruction, generated so it can not drift from the table
+pub fn reference() -> String {
+    let mut out = String::from("# Simpletron instruction set\n\n");
+    out.push_str("Generated from `isa/src/lib.rs` with `cargo run -p isa --bin reference > isa/ISA.md`, do not edit by hand.\n\n");
+    out.push_str(&format!(
+        "A word is a sign and 6 digits ({} to {:+}). An instruction is a 2 digit opcode and a 4 digit operand ({} to {:+}), the sign of the word is the sign of the operand.\n\n",
+        MIN_WORD, MAX_WORD, MIN_OPERAND, MAX_OPERAND
+    ));
+    out.push_str("| opcode | name | asml | semantics |\n");
+    out.push_str("|-------:|------|------|-----------|\n");
+    for instr in INSTRUCTIONS {
+        let forms: Vec<String> = instr.forms.iter()
+            .map(|form| {
+                let mut text = instr.mnemonic.to_string();
+                for arg in form.iter() {
+                    text.push(' ');
+                    text.push_str(arg.syntax());
+                }
+                format!("`{}`", text)
+            })
+            .collect();
+        let name = if instr.extension { format!("{} (extension)", instr.name) } else { instr.name.to_string() };
+        out.push_str(&format!("| {:02} | {} | {} | {} |\n", instr.opcode, name, forms.join(" "), instr.semantics));
+    }
+    out
+}
@@ -0,0 +1,4 @@
+// the simulator as a library, so the assembler's tests can run what it builds
+pub mod loader;
+pub mod simpletron;
+pub mod symbols;
@@ -0,0 +1,70 @@
+use isa::{MAX_OPERAND, MAX_WORD, MEMORY_SIZE, MIN_OPERAND};
+
+// relocatable images written by the assembler's --link --relocatable
+//
+// simpletron image <version>
+// words <count>          followed by one word per line, running starts at the first
+// reloc <offset> operand|word
+//
+// every word with a reloc holds an address counted from the start of the image, the operand
+// of an instruction or the whole word, loading at base adds base to each of them
+
+const IMAGE_VERSION: u32 = 1;
+
+pub fn is_image(lines: &[String]) -> bool {
+    lines.first().is_some_and(|line| line.trim().starts_with("simpletron image"))
+}
+
+// memory from address 0 with the image at base, the words before it are 0
+pub fn load_image(lines: &[String], base: i32) -> Result<Vec<i32>, String> {
+    let mut lines = lines.iter().map(|line| line.trim());
+
+    match lines.next().map(|line| line.split_whitespace().collect::<Vec<&str>>()).as_deref() {
+        Some(["simpletron", "image", version]) => match version.parse::<u32>() {
+            Ok(IMAGE_VERSION) => {}
+            _ => return Err(format!("Image version {} is not supported, this simulator loads version {}", version, IMAGE_VERSION)),
+        },
+        _ => return Err("Not a simpletron image".to_string()),
+    }
+
+    let count = match lines.next().map(|line| line.split_whitespace().collect::<Vec<&str>>()).as_deref() {
+        Some(["words", count]) => count.parse::<usize>().map_err(|_| format!("Invalid word count: {}", count))?,
+        _ => return Err("Missing the words record".to_string()),
+    };
+    if base < 0 || base as usize + count > MEMORY_SIZE {
+        return Err(format!("An image of {} words does not fit in memory at base {}", count, base));
+    }
+
+    let mut memory = vec![0; base as usize];
+    for _ in 0..count {
+        let line = lines.next().ok_or("The image has fewer words than its words record")?;
+        memory.push(line.parse::<i32>().map_err(|_| format!("Invalid word in image: {}", line))?);
+    }
+
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (offset, field) = match fields.as_slice() {
+            ["reloc", offset, field @ ("operand" | "word")] => match offset.parse::<usize>() {
+                Ok(offset) if offset < count => (offset, *field),
+                _ => return Err(format!("Invalid relocation: {}", line)),
+            },
+            _ => return Err(format!("Invalid image record: {}", line)),
+        };
+
+        let word = &mut memory[base as usize + offset];
+        if field == "operand" {
+            let (opcode, operand) = isa::decode(*word);
+            if operand + base > MAX_OPERAND || operand + base < MIN_OPERAND {
+                return Err(format!("The operand at image offset {} does not fit at base {}", offset, base));
+            }
+            *word = isa::encode(opcode, operand + base);
+        } else {
+            if *word + base > MAX_WORD {
+                return Err(format!("The word at image offset {} does not fit at base {}", offset, base));
+            }
+            *word += base;
+        }
+    }
+
+    Ok(memory)
+}
@@ -1,28 +1,71 @@
-mod simpletron;
-
-use simpletron::Simpletron;
+use clap::Parser;
+use sim::loader;
+use sim::simpletron::{self, Check, Fill, Simpletron};
+use sim::symbols::Symbols;
 use std::{io::Write, vec};
 
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// SML program to run, asked for when not given
+    program: Option<String>,
+    /// debug symbols written by the assembler's --symbols-file
+    #[arg(short, long)]
+    symbols: Option<String>,
+    /// print every instruction as it runs
+    #[arg(short, long)]
+    trace: bool,
+    /// load an image from the assembler's --link --relocatable at this address and start there
+    #[arg(short, long, default_value_t = 0)]
+    base: i32,
+    /// what to do when the program reads or runs a word it never loaded or stored
+    #[arg(long, value_enum, default_value_t = Check::Allow)]
+    uninitialized: Check,
+    /// what the words the program does not load start as: zero, random, sentinel or a word of your own
+    #[arg(long, default_value = "zero", value_parser = parse_fill)]
+    fill: Fill,
+    /// seed for --fill random, a new one each run otherwise
+    #[arg(long)]
+    seed: Option<u64>,
+    /// what to do when the program stores into an instruction that has already run
+    #[arg(long, value_enum, default_value_t = Check::Allow)]
+    self_modify: Check,
+}
+
 fn main() {
 
+    let args = Args::parse();
+
     println!("*** Welcome to Simpletron V2! ***");
     println!("***");
-    print!("Do you have a file that contains your SML program (Y/N) ? ");
-    std::io::stdout().flush().expect("Failed to flush stdout");
 
     let input = std::io::stdin();
-    let mut buffer = String::new();
-    input.read_line(&mut buffer).expect("Failed to read line");
-    let from_file = buffer.trim().to_lowercase() == "y";
+
+    let from_file = if args.program.is_some() {
+        true
+    } else {
+        print!("Do you have a file that contains your SML program (Y/N) ? ");
+        std::io::stdout().flush().expect("Failed to flush stdout");
+
+        let mut buffer = String::new();
+        input.read_line(&mut buffer).expect("Failed to read line");
+        buffer.trim().to_lowercase() == "y"
+    };
 
     let mut src_lines = Vec::new();
 
     if from_file {
-        print!("Please enter the file name: ");
-        std::io::stdout().flush().expect("Failed to flush stdout");
-        let mut file_name = String::new();
-        input.read_line(&mut file_name).expect("Failed to read line");
-        let file_name= file_name.trim();
+        let file_name = match &args.program {
+            Some(file_name) => file_name.clone(),
+            None => {
+                print!("Please enter the file name: ");
+                std::io::stdout().flush().expect("Failed to flush stdout");
+                let mut file_name = String::new();
+                input.read_line(&mut file_name).expect("Failed to read line");
+                file_name.trim().to_string()
+            }
+        };
+        let file_name = file_name.as_str();
 
         match std::fs::read_to_string(file_name) {
             Ok(contents) => {
@@ -55,9 +98,7 @@ fn main() {
     let mut clean_lines = vec![];
 
     for line in src_lines {
-        if line.trim().is_empty() {
-            continue;
-        } else if line.trim().starts_with(";") {
+        if line.trim().is_empty() || line.trim().starts_with(";") {
             continue;
         } else if line.contains(";") {
             let parts: Vec<&str> = line.splitn(2, ';').collect();
@@ -69,23 +110,63 @@ fn main() {
         }
     }
 
-    let instrs: Vec<i32> = clean_lines.iter().map(|line| {
-            match line.trim().parse::<i32>() {
-                Ok(num) => num,
-                Err(_) => {
-                    println!("Invalid instruction: {}", line);
-                    0
-                }
+    let instrs: Vec<i32> = if loader::is_image(&clean_lines) {
+        match loader::load_image(&clean_lines, args.base) {
+            Ok(memory) => memory,
+            Err(e) => {
+                println!("Error loading image: {}", e);
+                return;
             }
-    }).collect();
-        
+        }
+    } else if args.base != 0 {
+        println!("Error: a plain SML program always loads at 0, only an image from --link --relocatable can be moved");
+        return;
+    } else {
+        clean_lines.iter().map(|line| {
+                match line.trim().parse::<i32>() {
+                    Ok(num) => num,
+                    Err(_) => {
+                        println!("Invalid instruction: {}", line);
+                        0
+                    }
+                }
+        }).collect()
+    };
 
     let mut simpletron = Simpletron::new(instrs);
+    simpletron.start_at(args.base);
+    simpletron.mark_uninitialized(0..args.base);
+    simpletron.set_uninitialized_check(args.uninitialized);
+    simpletron.set_self_modify_check(args.self_modify);
+
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(1, |time| time.as_nanos() as u64)
+    });
+    if args.fill == Fill::Random {
+        println!("*** Memory filled with random words, --seed {} repeats them", seed);
+    }
+    simpletron.fill(args.fill, seed);
+
+    simpletron.add_extension_instruction(extension_opcode("WRITEC"), write_char_extention);
+    simpletron.add_extension_instruction(extension_opcode("ASSERT"), assert_extention);
+
+    if let Some(symbols_file) = &args.symbols {
+        let symbols = std::fs::read_to_string(symbols_file)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| Symbols::load(&contents))
+            .map(|symbols| symbols.moved(args.base));
+        match symbols {
+            Ok(symbols) => simpletron.set_symbols(symbols),
+            Err(e) => {
+                println!("Error reading symbols file: {}", e);
+                return;
+            }
+        }
+    }
 
-    simpletron.add_extension_instruction(12, write_char_extention);
-    simpletron.add_extension_instruction(1, assert_extention);
+    simpletron.set_trace(args.trace);
 
-    loop {
+    while !simpletron.is_halted() {
 
         simpletron.execute_step();
 
@@ -93,11 +174,26 @@ fn main() {
 
 }
 
+fn parse_fill(value: &str) -> Result<Fill, String> {
+    match value {
+        "zero" => Ok(Fill::Zero),
+        "random" => Ok(Fill::Random),
+        "sentinel" => Ok(Fill::Sentinel(simpletron::SENTINEL)),
+        _ => match value.parse::<i32>() {
+            Ok(word) if (isa::MIN_WORD..=isa::MAX_WORD).contains(&word) => Ok(Fill::Sentinel(word)),
+            _ => Err(format!("expected zero, random, sentinel or a word from {} to {}", isa::MIN_WORD, isa::MAX_WORD)),
+        },
+    }
+}
+
+fn extension_opcode(name: &str) -> u32 {
+    isa::opcode_of(name)
+}
+
 fn write_char_extention(simpletron: &mut Simpletron, _operand: i32) {
     let value = simpletron.get_memory(simpletron.get_acc());
     let c = (value as u8) as char;
-    print!("{}", c);
-    std::io::stdout().flush().expect("Failed to flush stdout");
+    simpletron.print(&c.to_string());
 }
 
 fn assert_extention(simpletron: &mut Simpletron, operand: i32) {
@@ -105,13 +201,13 @@ fn assert_extention(simpletron: &mut Simpletron, operand: i32) {
         if simpletron.get_acc() != operand {
             simpletron.dump_regs();
             simpletron.dump_memory(0, 0);
-            assert!(false, "Assert failed: ACC ({}) != operand ({})", simpletron.get_acc(), operand);
+            panic!("Assert failed: ACC ({}) != operand ({})", simpletron.get_acc(), operand);
         }
     } else {
         if simpletron.get_acc() != simpletron.get_ix() {
             simpletron.dump_regs();
             simpletron.dump_memory(0, 0);
-            assert!(false, "Assert failed: ACC ({}) != IX ({})", simpletron.get_acc(), simpletron.get_ix());
+            panic!("Assert failed: ACC ({}) != IX ({})", simpletron.get_acc(), simpletron.get_ix());
         }
     }
 }
\ No newline at end of file
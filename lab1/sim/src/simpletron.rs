@@ -1,19 +1,54 @@
+use std::collections::{BTreeSet, VecDeque};
 use std::io::Write;
+use std::ops::Range;
 
-const MEMORY_PAGES: usize = 100;
-const MEMORY_WORDS_PER_PAGE: usize = 100;
+use isa::{MAX_WORD, MEMORY_PAGES, MIN_WORD};
 
-const MAX_MEMORY: usize = MEMORY_PAGES * MEMORY_WORDS_PER_PAGE;
+use crate::symbols::Symbols;
 
-const MAX_WORD: i32 = 999999;
-const MIN_WORD: i32 = -999999;
+const MEMORY_WORDS_PER_PAGE: usize = isa::WORDS_PER_PAGE;
+
+const MAX_MEMORY: usize = isa::MEMORY_SIZE;
 
 // i32 = operand
 type InstHandler = fn(&mut Simpletron, i32);
-const INSTR_HANDLER_COUNT: usize = 50;
+const INSTR_HANDLER_COUNT: usize = isa::MAX_OPCODE as usize + 1;
+
+// what --fill sentinel puts in memory, it decodes to opcode 99 and overflows almost anything added to it
+pub const SENTINEL: i32 = MIN_WORD;
+
+// what to do when the program does something suspicious
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Check {
+    // carry on as the hardware would
+    Allow,
+    // report it once and carry on
+    Warn,
+    // stop like any other error
+    Fault,
+}
+
+// what memory the program did not load starts as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+    Zero,
+    Random,
+    Sentinel(i32),
+}
 
 pub struct Simpletron {
     memory: [[i32; MEMORY_WORDS_PER_PAGE]; MEMORY_PAGES],
+    // shadow memory, whether each word was loaded with the program or stored since
+    initialized: [[bool; MEMORY_WORDS_PER_PAGE]; MEMORY_PAGES],
+    // reading or running a word that is not initialized
+    uninitialized_check: Check,
+    // whether each word has been fetched as an instruction
+    fetched: [[bool; MEMORY_WORDS_PER_PAGE]; MEMORY_PAGES],
+    // storing into a word that has run
+    self_modify_check: Check,
+    // the instructions already warned about being overwritten
+    self_modified: BTreeSet<i32>,
+
     // accumulator
     acc: i32,
 
@@ -30,31 +65,51 @@ pub struct Simpletron {
     handlers: [Option<InstHandler>; INSTR_HANDLER_COUNT],
 
     is_halted: bool,
+
+    // address of the instruction being executed, ip has already moved past it
+    instr_addr: i32,
+
+    // debug symbols from the assembler, used to name addresses in errors and dumps
+    symbols: Option<Symbols>,
+
+    // print every instruction as it executes
+    trace: bool,
+
+    // words for READ instead of the terminal, when given
+    input: Option<VecDeque<i32>>,
+    // what WRITE and the host print, kept instead of printed when captured
+    output: Option<String>,
 }
 
 impl Simpletron {
     pub fn new(instructions: Vec<i32>) -> Self {
         let mut simple = Simpletron {
             memory: [[0; MEMORY_WORDS_PER_PAGE]; MEMORY_PAGES],
+            initialized: [[false; MEMORY_WORDS_PER_PAGE]; MEMORY_PAGES],
+            uninitialized_check: Check::Allow,
+            fetched: [[false; MEMORY_WORDS_PER_PAGE]; MEMORY_PAGES],
+            self_modify_check: Check::Allow,
+            self_modified: BTreeSet::new(),
             acc: 0,
             ix: 0,
             ip: 0,
             ir: 0,
-            handlers: [
-                None,           None,               None,               None,           None,       // 0
-                None,           None,               None,               None,           None,       // 5
-                Some(read),     Some(write),        None,               None,           None,       // 10
-                None,           None,               None,               None,           None,       // 15
-                Some(load),     Some(load_im),      Some(load_x),       Some(load_idx), None,       // 20
-                Some(store),    Some(store_idx),    None,               None,           None,       // 25
-                Some(add),      Some(add_x),        Some(sub),          Some(sub_x),    Some(div),  // 30
-                Some(div_x),    Some(mul),          Some(mul_x),        Some(inc),      Some(dec),  // 35
-                Some(branch),   Some(branch_neg),   Some(branch_zero),  Some(swap),     None,       // 40
-                Some(halt),     None,               None,               None,           None        // 45
-            ],
+            handlers: [None; INSTR_HANDLER_COUNT],
             is_halted: false,
+            instr_addr: 0,
+            symbols: None,
+            trace: false,
+            input: None,
+            output: None,
         };
 
+        // every built in instruction of the ISA must have a handler here, extensions are added by the host
+        for instr in isa::INSTRUCTIONS.iter().filter(|instr| !instr.extension) {
+            let handler = builtin_handler(instr.name)
+                .unwrap_or_else(|| panic!("No handler for {} (opcode {})", instr.name, instr.opcode));
+            simple.handlers[instr.opcode as usize] = Some(handler);
+        }
+
         // Copy instructions into memory
         for (i, &instr) in instructions.iter().enumerate() {
             if i < MEMORY_PAGES * MEMORY_WORDS_PER_PAGE {
@@ -73,27 +128,168 @@ impl Simpletron {
             return;
         }
 
-        let instr = self.get_memory(self.ip);
+        self.instr_addr = self.ip;
+        if !self.is_initialized(self.ip) {
+            // the location of the fault is the word itself
+            self.uninitialized(self.ip, "Execution of uninitialized memory".to_string());
+            if self.is_halted {
+                return;
+            }
+        }
+        let instr = self.peek_memory(self.ip);
+        self.ir = instr;
+        let (page, offset) = calculate_page_address(self.ip);
+        self.fetched[page as usize][offset as usize] = true;
+
+        if self.trace {
+            println!("{:04} {:+07} {}", self.instr_addr, instr, self.symbols.as_ref().and_then(|symbols| symbols.describe(self.instr_addr)).unwrap_or_default());
+        }
 
         self.set_ip(self.ip + 1);
 
-        let opcode = read_opcode(instr);
-        let operand = read_operand(instr);
+        let (opcode, operand) = isa::decode(instr);
 
-        if opcode < 0 || opcode >= INSTR_HANDLER_COUNT as i32 {
-            eprintln!("Error: Invalid opcode {}\nHalting", opcode);
-            self.is_halted = true;
+        if opcode >= INSTR_HANDLER_COUNT as u32 {
+            self.fault(format!("Invalid opcode {}", opcode));
             return;
         }
 
         if let Some(handler) = self.handlers[opcode as usize] {
             handler(self, operand);
         } else {
-            eprintln!("Error: Unimplemented opcode {}\nHalting", opcode);
-            self.is_halted = true;
-            return;
+            self.fault(format!("Unimplemented opcode {}", opcode));
+        }
+
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.is_halted
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = Some(symbols);
+    }
+
+    // where the first instruction is fetched from, 0 unless the program was loaded elsewhere
+    pub fn start_at(&mut self, addr: i32) {
+        self.set_ip(addr);
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    // READ takes these words in turn instead of asking, running out is a fault
+    pub fn set_input(&mut self, words: Vec<i32>) {
+        self.input = Some(words.into());
+    }
+
+    // keep what the program prints for output() instead of printing it
+    pub fn capture_output(&mut self) {
+        self.output = Some(String::new());
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_deref().unwrap_or_default()
+    }
+
+    // the program's output, to the terminal unless it is captured
+    pub fn print(&mut self, text: &str) {
+        match self.output.as_mut() {
+            Some(output) => output.push_str(text),
+            None => {
+                print!("{}", text);
+                std::io::stdout().flush().expect("Failed to flush stdout");
+            }
+        }
+    }
+
+    pub fn set_uninitialized_check(&mut self, check: Check) {
+        self.uninitialized_check = check;
+    }
+
+    pub fn set_self_modify_check(&mut self, check: Check) {
+        self.self_modify_check = check;
+    }
+
+    // words that hold something but are not part of the program, the ones before an image loaded at a base
+    pub fn mark_uninitialized(&mut self, addrs: Range<i32>) {
+        for addr in addrs {
+            let (page, offset) = calculate_page_address(addr);
+            self.initialized[page as usize][offset as usize] = false;
+        }
+    }
+
+    // puts the fill in every word the program did not load, they stay uninitialized
+    pub fn fill(&mut self, fill: Fill, seed: u64) {
+        // xorshift, the same seed gives the same memory
+        let mut state = seed.max(1);
+        for addr in 0..MAX_MEMORY as i32 {
+            if self.is_initialized(addr) {
+                continue;
+            }
+            let value = match fill {
+                Fill::Zero => 0,
+                Fill::Sentinel(value) => value,
+                Fill::Random => {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    (state % (MAX_WORD - MIN_WORD + 1) as u64) as i32 + MIN_WORD
+                }
+            };
+            let (page, offset) = calculate_page_address(addr);
+            self.memory[page as usize][offset as usize] = value;
+        }
+    }
+
+    // gcd.asml:22 (in `else`) when symbols are loaded, the raw address otherwise
+    fn location(&self, addr: i32) -> String {
+        match self.symbols.as_ref().and_then(|symbols| symbols.describe(addr)) {
+            Some(location) => location,
+            None => format!("address {}", addr),
+        }
+    }
+
+    // address 82 (`count`) when symbols name it
+    fn address_name(&self, addr: i32) -> String {
+        let names: Vec<&str> = self.symbols.as_ref()
+            .map(|symbols| symbols.names_in(addr, addr).into_iter().map(|(_, name)| name).collect())
+            .unwrap_or_default();
+        if names.is_empty() {
+            format!("address {}", addr)
+        } else {
+            format!("address {} (`{}`)", addr, names.join("`, `"))
         }
+    }
+
+    // stops the machine, reporting where the faulting instruction came from
+    fn fault(&mut self, message: String) {
+        eprintln!("Error: {} at {}\nHalting", message, self.location(self.instr_addr));
+        self.is_halted = true;
+    }
+
+    // reports like fault and carries on
+    fn warn(&self, message: String) {
+        eprintln!("Warning: {} at {}", message, self.location(self.instr_addr));
+    }
+
+    fn is_initialized(&self, addr: i32) -> bool {
+        let (page, offset) = calculate_page_address(addr);
+        self.initialized[page as usize][offset as usize]
+    }
 
+    // what reading or running an uninitialized word does, a warning is only given once for each word
+    fn uninitialized(&mut self, addr: i32, message: String) {
+        match self.uninitialized_check {
+            Check::Allow => {}
+            Check::Warn => {
+                self.warn(message);
+                let (page, offset) = calculate_page_address(addr);
+                self.initialized[page as usize][offset as usize] = true;
+            }
+            Check::Fault => self.fault(message),
+        }
     }
 
     pub fn get_acc(&self) -> i32 {
@@ -102,9 +298,8 @@ impl Simpletron {
 
     fn set_acc(&mut self, value: i32) {
 
-        if value < MIN_WORD || value > MAX_WORD {
-            eprintln!("Error: Accumulator overflow/underflow with value {}\nHalting", value);
-            self.is_halted = true;
+        if !(MIN_WORD..=MAX_WORD).contains(&value) {
+            self.fault(format!("Accumulator overflow/underflow with value {}", value));
             return;
         }
 
@@ -117,9 +312,8 @@ impl Simpletron {
 
     fn set_idx(&mut self, value: i32) {
 
-        if value < MIN_WORD || value > MAX_WORD {
-            eprintln!("Error: Index register overflow/underflow with value {}\nHalting", value);
-            self.is_halted = true;
+        if !(MIN_WORD..=MAX_WORD).contains(&value) {
+            self.fault(format!("Index register overflow/underflow with value {}", value));
             return;
         }
 
@@ -129,8 +323,7 @@ impl Simpletron {
     fn set_ip(&mut self, value: i32) {
 
         if value < 0 || value >= MAX_MEMORY as i32 {
-            eprintln!("Error: Instruction pointer out of bounds with value {}\nHalting", value);
-            self.is_halted = true;
+            self.fault(format!("Instruction pointer out of bounds with value {}", value));
             return;
         }
 
@@ -140,33 +333,53 @@ impl Simpletron {
     pub fn get_memory(&mut self, index: i32) -> i32 {
 
         if index < 0 || index >= MAX_MEMORY as i32 {
-            eprintln!("Error: Memory access out of bounds at address {}\nHalting", index);
-            self.is_halted = true;
+            self.fault(format!("Memory access out of bounds (address {})", index));
             return 0;
         }
 
-        let (page, offset) = calculate_page_address(index);
+        if !self.is_initialized(index) {
+            self.uninitialized(index, format!("Read of uninitialized memory at {}", self.address_name(index)));
+        }
+
+        self.peek_memory(index)
+    }
 
+    // the word without any checks, for fetching and dumps
+    fn peek_memory(&self, index: i32) -> i32 {
+        let (page, offset) = calculate_page_address(index);
         self.memory[page as usize][offset as usize]
     }
 
     pub fn set_memory(&mut self, index: i32, value: i32) {
 
         if index < 0 || index >= MAX_MEMORY as i32 {
-            eprintln!("Error: Memory access out of bounds at address {}\nHalting", index);
-            self.is_halted = true;
+            self.fault(format!("Memory access out of bounds (address {})", index));
             return;
         }
 
-        if value < MIN_WORD || value > MAX_WORD {
-            eprintln!("Error: Memory value overflow/underflow with value {}\nHalting", value);
-            self.is_halted = true;
+        if !(MIN_WORD..=MAX_WORD).contains(&value) {
+            self.fault(format!("Memory value overflow/underflow with value {}", value));
             return;
         }
 
         let (page, offset) = calculate_page_address(index);
 
+        // a store into an instruction that has already run, a warning is only given once for each word
+        if self.fetched[page as usize][offset as usize] && self.self_modify_check != Check::Allow {
+            let writer = isa::by_opcode(isa::decode(self.ir).0).map_or("?", |instr| instr.name);
+            let message = format!("{} {:+07} overwrote the instruction at {} ({:+07} becomes {:+07})",
+                writer, self.ir, self.address_name(index), self.memory[page as usize][offset as usize], value);
+            if self.self_modify_check == Check::Fault {
+                self.fault(message);
+                return;
+            }
+            if self.self_modified.insert(index) {
+                self.warn(message);
+            }
+        }
+
         self.memory[page as usize][offset as usize] = value;
+        self.initialized[page as usize][offset as usize] = true;
     }
 
     pub fn dump_regs(&self) {
@@ -174,8 +387,11 @@ impl Simpletron {
         println!("Accumulator:          {:+07}",        self.acc);
         println!("InstructionCounter:   {:+07}",        self.ip);
         println!("IndexRegister:        {:+07}",        self.ix);
-        println!("operationCode:             {:02}",    read_opcode(self.ir));
-        println!("operand:                {:+05}",      read_operand(self.ir));
+        println!("operationCode:             {:02}",    isa::decode(self.ir).0);
+        println!("operand:                {:+05}",      isa::decode(self.ir).1);
+        if self.symbols.is_some() {
+            println!("Location:             {}",            self.location(self.instr_addr));
+        }
     }
 
     pub fn dump_memory(&mut self, start_page: usize, end_page: usize) {
@@ -196,10 +412,21 @@ impl Simpletron {
                     print!("{:02} ", offset);
                 }
                 let address = page * MEMORY_WORDS_PER_PAGE + offset;
-                let value = self.get_memory(address as i32);
+                let value = self.peek_memory(address as i32);
                 print!("{:+07} ", value);
             }
             println!();
+
+            if let Some(symbols) = &self.symbols {
+                let first = (page * MEMORY_WORDS_PER_PAGE) as i32;
+                let names: Vec<String> = symbols.names_in(first, first + MEMORY_WORDS_PER_PAGE as i32 - 1)
+                    .iter()
+                    .map(|(addr, name)| format!("{}={:04}", name, addr))
+                    .collect();
+                if !names.is_empty() {
+                    println!("Symbols: {}", names.join(" "));
+                }
+            }
         }
     }
 
@@ -210,25 +437,67 @@ impl Simpletron {
 }
 
 
-fn read_opcode(instr: i32) -> i32 {
-    (instr / 10000).abs()
-}
-
-fn read_operand(instr: i32) -> i32 {
-    let sign = if instr < 0 { -1 } else { 1 };
-    let operand = (instr % 10000).abs();
-    sign * operand
+// the handler for a built in instruction, by its name in the ISA
+fn builtin_handler(name: &str) -> Option<InstHandler> {
+    let handler: InstHandler = match name {
+        "READ" => read,
+        "WRITE" => write,
+        "LOAD" => load,
+        "LOADIM" => load_im,
+        "LOADX" => load_x,
+        "LOADIDX" => load_idx,
+        "STORE" => store,
+        "STOREIDX" => store_idx,
+        "ADD" => add,
+        "ADDX" => add_x,
+        "SUBTRACT" => sub,
+        "SUBTRACTX" => sub_x,
+        "DIVIDE" => div,
+        "DIVIDEX" => div_x,
+        "MULTIPLY" => mul,
+        "MULTIPLYX" => mul_x,
+        "INC" => inc,
+        "DEC" => dec,
+        "BRANCH" => branch,
+        "BRANCHNEG" => branch_neg,
+        "BRANCHZERO" => branch_zero,
+        "SWAP" => swap,
+        "HALT" => halt,
+        "LOADOFF" => load_off,
+        "STOREOFF" => store_off,
+        "ADDOFF" => add_off,
+        "SUBTRACTOFF" => sub_off,
+        "DIVIDEOFF" => div_off,
+        "MULTIPLYOFF" => mul_off,
+        "LOADIND" => load_ind,
+        "STOREIND" => store_ind,
+        "BRANCHX" => branch_x,
+        "BRANCHIND" => branch_ind,
+        "BRANCHREL" => branch_rel,
+        "BRANCHNEGREL" => branch_neg_rel,
+        "BRANCHZEROREL" => branch_zero_rel,
+        _ => return None,
+    };
+    Some(handler)
 }
 
 // page, offset
 fn calculate_page_address(index: i32) -> (i32, i32) {
-    return (index / MEMORY_WORDS_PER_PAGE as i32, index % MEMORY_WORDS_PER_PAGE as i32);
+    (index / MEMORY_WORDS_PER_PAGE as i32, index % MEMORY_WORDS_PER_PAGE as i32)
 }
 
 
 // READ=10 - Read a word from the terminal into a location whose address is the operand
 fn read(simpletron: &mut Simpletron, operand: i32) {
 
+    if let Some(input) = simpletron.input.as_mut() {
+        match input.pop_front() {
+            Some(value) => simpletron.set_memory(operand, value),
+            None => simpletron.fault("Read with no input left".to_string()),
+        }
+        return;
+    }
+
     loop {
 
         print!("? ");
@@ -236,7 +505,7 @@ fn read(simpletron: &mut Simpletron, operand: i32) {
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).expect("Failed to read line");
         let input = input.trim();
-        print!("\n");
+        println!();
 
         match input.trim().parse::<i32>() {
             Ok(value) => {
@@ -255,7 +524,7 @@ fn read(simpletron: &mut Simpletron, operand: i32) {
 fn write(simpletron: &mut Simpletron, operand: i32) {
 
     let value = simpletron.get_memory(operand);
-    print!("=> {}\n", value);
+    simpletron.print(&format!("=> {}\n", value));
 
 }
 
@@ -341,8 +610,7 @@ fn div(simpletron: &mut Simpletron, operand: i32) {
 
     let value = simpletron.get_memory(operand);
     if value == 0 {
-        eprintln!("Error: Division by zero at address {}\nHalting", operand);
-        simpletron.is_halted = true;
+        simpletron.fault(format!("Division by zero (divisor at address {})", operand));
         return;
     }
     simpletron.set_acc(simpletron.get_acc() / value);
@@ -354,8 +622,7 @@ fn div_x(simpletron: &mut Simpletron, _: i32) {
 
     let value = simpletron.get_memory(simpletron.ix);
     if value == 0 {
-        eprintln!("Error: Division by zero at address in index register {}\nHalting", simpletron.ix);
-        simpletron.is_halted = true;
+        simpletron.fault(format!("Division by zero (divisor at address {} from the index register)", simpletron.ix));
         return;
     }
 
@@ -425,19 +692,273 @@ fn branch_zero(simpletron: &mut Simpletron, operand: i32) {
 // SWAP=43 - Swap contents of index register and accumulator 
 fn swap(simpletron: &mut Simpletron, _: i32) {
 
-    // xor swap 
-    simpletron.acc ^= simpletron.ix;
-    simpletron.ix ^= simpletron.acc;
-    simpletron.acc ^= simpletron.ix;
+    std::mem::swap(&mut simpletron.acc, &mut simpletron.ix);
     
 }
 
 // HALT=45 - Halt program dump register values and a range of pages. The starting page of the range is stored as the top 2 digits of the operand and the last page as the least significant 2 digits (core dump). 
 fn halt(simpletron: &mut Simpletron, operand: i32) {
     simpletron.dump_regs();
-    let start_page = (operand / 100).abs() as usize;
-    let end_page = (operand % 100).abs() as usize;
+    let start_page = (operand / 100).unsigned_abs() as usize;
+    let end_page = (operand % 100).unsigned_abs() as usize;
     simpletron.dump_memory(start_page, end_page);
     println!("Program halted.");
     simpletron.is_halted = true;
 }
+
+// LOADOFF=50 - Load a word from the memory location the index register plus the operand points at into the accumulator
+fn load_off(simpletron: &mut Simpletron, operand: i32) {
+
+    let value = simpletron.get_memory(simpletron.ix + operand);
+    simpletron.set_acc(value);
+
+}
+
+// STOREOFF=51 - Store the accumulator into the memory location the index register plus the operand points at
+fn store_off(simpletron: &mut Simpletron, operand: i32) {
+
+    simpletron.set_memory(simpletron.ix + operand, simpletron.acc);
+
+}
+
+// ADDOFF=52 - Add the word at the index register plus the operand to the accumulator
+fn add_off(simpletron: &mut Simpletron, operand: i32) {
+
+    let value = simpletron.get_memory(simpletron.ix + operand);
+    simpletron.set_acc(simpletron.get_acc() + value);
+
+}
+
+// SUBTRACTOFF=53 - Subtract the word at the index register plus the operand from the accumulator
+fn sub_off(simpletron: &mut Simpletron, operand: i32) {
+
+    let value = simpletron.get_memory(simpletron.ix + operand);
+    simpletron.set_acc(simpletron.get_acc() - value);
+
+}
+
+// DIVIDEOFF=54 - Divide the accumulator by the word at the index register plus the operand and lose the remainder
+fn div_off(simpletron: &mut Simpletron, operand: i32) {
+
+    let address = simpletron.ix + operand;
+    let value = simpletron.get_memory(address);
+    if value == 0 {
+        simpletron.fault(format!("Division by zero (divisor at address {} from the index register plus {})", address, operand));
+        return;
+    }
+    simpletron.set_acc(simpletron.get_acc() / value);
+
+}
+
+// MULTIPLYOFF=55 - Multiply the accumulator by the word at the index register plus the operand
+fn mul_off(simpletron: &mut Simpletron, operand: i32) {
+
+    let value = simpletron.get_memory(simpletron.ix + operand);
+    simpletron.set_acc(simpletron.get_acc() * value);
+
+}
+
+// LOADIND=56 - Load into the accumulator the word whose address is stored at the location given by the operand ( ACC = MEM[MEM] )
+fn load_ind(simpletron: &mut Simpletron, operand: i32) {
+
+    let address = simpletron.get_memory(operand);
+    let value = simpletron.get_memory(address);
+    simpletron.set_acc(value);
+
+}
+
+// STOREIND=57 - Store the accumulator at the address stored at the location given by the operand ( MEM[MEM] = ACC )
+fn store_ind(simpletron: &mut Simpletron, operand: i32) {
+
+    let address = simpletron.get_memory(operand);
+    simpletron.set_memory(address, simpletron.acc);
+
+}
+
+// BRANCHX=58 - Branch to the location in the index register
+fn branch_x(simpletron: &mut Simpletron, _: i32) {
+
+    simpletron.set_ip(simpletron.ix);
+
+}
+
+// BRANCHIND=59 - Branch to the location stored at the location given by the operand
+fn branch_ind(simpletron: &mut Simpletron, operand: i32) {
+
+    let address = simpletron.get_memory(operand);
+    simpletron.set_ip(address);
+
+}
+
+// BRANCHREL=60 - Branch to the location operand words away from this instruction, the operand can be negative
+fn branch_rel(simpletron: &mut Simpletron, operand: i32) {
+
+    simpletron.set_ip(simpletron.instr_addr + operand);
+
+}
+
+// BRANCHNEGREL=61 - Branch operand words away from this instruction if accumulator is negative
+fn branch_neg_rel(simpletron: &mut Simpletron, operand: i32) {
+
+    if simpletron.acc < 0 {
+
+        simpletron.set_ip(simpletron.instr_addr + operand);
+
+    }
+
+}
+
+// BRANCHZEROREL=62 - Branch operand words away from this instruction if the accumulator is zero
+fn branch_zero_rel(simpletron: &mut Simpletron, operand: i32) {
+
+    if simpletron.acc == 0 {
+
+        simpletron.set_ip(simpletron.instr_addr + operand);
+
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader;
+
+    // nothing here reaches a hlt, so no dump is printed
+    fn machine(words: &[i32], uninitialized: Check) -> Simpletron {
+        let mut simpletron = Simpletron::new(words.to_vec());
+        simpletron.set_uninitialized_check(uninitialized);
+        simpletron
+    }
+
+    fn run(simpletron: &mut Simpletron, steps: usize) {
+        for _ in 0..steps {
+            simpletron.execute_step();
+        }
+    }
+
+    // mov acc [50], mov acc 7
+    const READ_50: [i32; 2] = [200050, 210007];
+
+    #[test]
+    fn uninitialized_read_allowed() {
+        let mut simpletron = machine(&READ_50, Check::Allow);
+        run(&mut simpletron, 2);
+        assert!(!simpletron.is_halted());
+        assert_eq!(simpletron.get_acc(), 7);
+        assert!(!simpletron.is_initialized(50));
+    }
+
+    #[test]
+    fn uninitialized_read_warns_once() {
+        let mut simpletron = machine(&READ_50, Check::Warn);
+        run(&mut simpletron, 2);
+        assert!(!simpletron.is_halted());
+        assert_eq!(simpletron.get_acc(), 7);
+        // the next read of 50 is not reported again
+        assert!(simpletron.is_initialized(50));
+    }
+
+    #[test]
+    fn uninitialized_read_faults() {
+        let mut simpletron = machine(&READ_50, Check::Fault);
+        run(&mut simpletron, 2);
+        assert!(simpletron.is_halted());
+        assert_eq!(simpletron.instr_addr, 0);
+        assert_eq!(simpletron.get_acc(), 0);
+    }
+
+    #[test]
+    fn uninitialized_execution_faults() {
+        // jmp 50
+        let mut simpletron = machine(&[400050], Check::Fault);
+        run(&mut simpletron, 2);
+        assert!(simpletron.is_halted());
+        assert_eq!(simpletron.instr_addr, 50);
+    }
+
+    #[test]
+    fn words_before_a_relocated_image_are_uninitialized() {
+        // mov acc [0], mov acc [5] loaded at 10, the first is moved to mov acc [10]
+        let lines: Vec<String> = ["simpletron image 1", "words 2", "+200000", "+200005", "reloc 0 operand"]
+            .iter().map(|line| line.to_string()).collect();
+        let mut simpletron = machine(&loader::load_image(&lines, 10).unwrap(), Check::Fault);
+        simpletron.start_at(10);
+        simpletron.mark_uninitialized(0..10);
+        assert!((0..10).all(|addr| !simpletron.is_initialized(addr)));
+        assert!(simpletron.is_initialized(10) && simpletron.is_initialized(11));
+
+        run(&mut simpletron, 1);
+        assert!(!simpletron.is_halted());
+        assert_eq!(simpletron.get_acc(), 200010);
+        run(&mut simpletron, 1);
+        assert!(simpletron.is_halted());
+        assert_eq!(simpletron.instr_addr, 11);
+    }
+
+    #[test]
+    fn the_same_seed_fills_the_same() {
+        let memory = |seed| {
+            let mut simpletron = machine(&READ_50, Check::Allow);
+            simpletron.fill(Fill::Random, seed);
+            simpletron.memory
+        };
+        assert_eq!(memory(42), memory(42));
+        assert_ne!(memory(42), memory(43));
+        // the program is left alone
+        assert_eq!(memory(42)[0][..2], READ_50);
+    }
+
+    #[test]
+    fn sentinel_fill() {
+        let mut simpletron = machine(&READ_50, Check::Allow);
+        simpletron.fill(Fill::Sentinel(SENTINEL), 0);
+        assert_eq!(simpletron.peek_memory(50), -999999);
+        assert_eq!(simpletron.peek_memory(MAX_MEMORY as i32 - 1), -999999);
+        assert_eq!(simpletron.peek_memory(0), READ_50[0]);
+        // filled words are still uninitialized
+        assert!(!simpletron.is_initialized(50));
+        run(&mut simpletron, 1);
+        assert_eq!(simpletron.get_acc(), -999999);
+    }
+
+    // mov acc 5, mov [3] acc before 3 has run, mov [0] acc after 0 has, mov acc 7
+    const OVERWRITE_0: [i32; 4] = [210005, 250003, 250000, 210007];
+
+    fn self_modifying(check: Check) -> Simpletron {
+        let mut simpletron = machine(&OVERWRITE_0, Check::Allow);
+        simpletron.set_self_modify_check(check);
+        simpletron
+    }
+
+    #[test]
+    fn self_modify_allowed() {
+        let mut simpletron = self_modifying(Check::Allow);
+        run(&mut simpletron, 3);
+        assert!(!simpletron.is_halted());
+        assert_eq!(simpletron.peek_memory(0), 5);
+        assert!(simpletron.self_modified.is_empty());
+    }
+
+    #[test]
+    fn self_modify_warns_about_fetched_words_only() {
+        let mut simpletron = self_modifying(Check::Warn);
+        run(&mut simpletron, 3);
+        assert!(!simpletron.is_halted());
+        assert_eq!(simpletron.peek_memory(0), 5);
+        // 3 had not run when it was stored into
+        assert_eq!(simpletron.self_modified, BTreeSet::from([0]));
+    }
+
+    #[test]
+    fn self_modify_faults_before_the_store() {
+        let mut simpletron = self_modifying(Check::Fault);
+        run(&mut simpletron, 2);
+        assert!(!simpletron.is_halted());
+        assert_eq!(simpletron.peek_memory(3), 5);
+        run(&mut simpletron, 1);
+        assert!(simpletron.is_halted());
+        assert_eq!(simpletron.instr_addr, 2);
+        assert_eq!(simpletron.peek_memory(0), OVERWRITE_0[0]);
+    }
+}
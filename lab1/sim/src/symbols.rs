@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+// debug symbols written by the assembler's --symbols-file
+pub struct Symbols {
+    // address -> (file, line, enclosing label)
+    lines: BTreeMap<i32, (String, usize, Option<String>)>,
+    // address -> names, labels and data symbols alike
+    names: BTreeMap<i32, Vec<String>>,
+}
+
+impl Symbols {
+    pub fn load(contents: &str) -> Result<Self, String> {
+        let mut symbols = Symbols { lines: BTreeMap::new(), names: BTreeMap::new() };
+
+        for (line_num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.splitn(5, ' ').collect();
+            let bad_record = || format!("Invalid symbol record on line {}: {}", line_num + 1, line);
+
+            match parts.as_slice() {
+                // line <addr> <line> <label or -> <file>
+                ["line", addr, source_line, scope, file] => {
+                    let addr = addr.parse::<i32>().map_err(|_| bad_record())?;
+                    let source_line = source_line.parse::<usize>().map_err(|_| bad_record())?;
+                    let scope = if *scope == "-" { None } else { Some(scope.to_string()) };
+                    symbols.lines.insert(addr, (file.to_string(), source_line, scope));
+                }
+                // label <name> <addr>, data <name> <addr>
+                ["label" | "data", name, addr] => {
+                    let addr = addr.parse::<i32>().map_err(|_| bad_record())?;
+                    symbols.names.entry(addr).or_default().push(name.to_string());
+                }
+                _ => return Err(bad_record()),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    // the same symbols for a program loaded at base instead of 0
+    pub fn moved(self, base: i32) -> Self {
+        Symbols {
+            lines: self.lines.into_iter().map(|(addr, line)| (addr + base, line)).collect(),
+            names: self.names.into_iter().map(|(addr, names)| (addr + base, names)).collect(),
+        }
+    }
+
+    // gcd.asml:22 (in `else`)
+    pub fn describe(&self, addr: i32) -> Option<String> {
+        let (file, line, scope) = self.lines.get(&addr)?;
+        match scope {
+            Some(scope) => Some(format!("{}:{} (in `{}`)", file, line, scope)),
+            None => Some(format!("{}:{}", file, line)),
+        }
+    }
+
+    // labels and data symbols between two addresses, for annotating dumps
+    pub fn names_in(&self, start: i32, end: i32) -> Vec<(i32, &str)> {
+        self.names.range(start..=end)
+            .flat_map(|(&addr, names)| names.iter().map(move |name| (addr, name.as_str())))
+            .collect()
+    }
+}
@@ -0,0 +1,363 @@
+use crate::lexer::{tokenize, Token};
+use crate::postfix::{to_postfix, variable_name, Item, Op, Operand};
+use crate::symbols::{Symbol, SymbolTable};
+
+// Simple to asml
+//
+// every line starts with its line number, they go up through the program
+// 10 rem anything           a comment
+// 20 input x, y             read a number into each variable
+// 30 let z = (x + y) / 2    work out an expression, + - * / and ( )
+// 40 print z, z * 2         write the value of each expression
+// 50 if x >= y goto 70      == != < <= > >=, compares the two expressions
+// 60 goto 30
+// 70 end                    stop
+//
+// variables need no declaration, they start at 0, names are a letter then letters, digits and _
+// each line gets the label line10 ... so goto 30 is jmp line30
+// an expression leaves its value in acc, operands are data words, ie x + 1 is
+//     mov acc [var_x]
+//     add acc [const_1]
+// an if works out left - right and branches on acc with jlz and jez
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    MissingLineNumber,
+    // line numbers have to go up
+    LineOutOfOrder(i32),
+    UnknownStatement(String),
+    SyntaxError,
+    UnexpectedCharacter(char),
+    InvalidNumber(String),
+    InvalidVariable(String),
+    UnbalancedParentheses,
+    UndefinedLine(i32),
+}
+
+pub struct Compiled {
+    pub asml: String,
+    pub table: SymbolTable,
+    // (error, source line, line in the file from 0)
+    pub errors: Vec<(CompileError, String, usize)>,
+}
+
+// a value on the stack while an expression is worked out
+enum Value {
+    // a data word
+    Slot(String),
+    Acc,
+}
+
+struct Compiler {
+    out: String,
+    table: SymbolTable,
+}
+
+pub fn compile(source: &str, name: &str) -> Compiled {
+    let mut compiler = Compiler { out: format!("; compiled from {} by simplec\n\n", name), table: SymbolTable::default() };
+    let mut errors = Vec::new();
+
+    // every line number first, so goto can go forwards
+    let mut lines = Vec::new();
+    let mut last = None;
+    for (line_num, line) in source.lines().enumerate() {
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let (number, rest) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+        let number = match number.parse::<i32>() {
+            Ok(number) => number,
+            Err(_) => {
+                errors.push((CompileError::MissingLineNumber, text.to_string(), line_num));
+                continue;
+            }
+        };
+        if last.is_some_and(|last| number <= last) {
+            errors.push((CompileError::LineOutOfOrder(number), text.to_string(), line_num));
+            continue;
+        }
+        last = Some(number);
+        compiler.table.add(Symbol::Line(number));
+        lines.push((line_num, number, text, rest.trim()));
+    }
+
+    for (line_num, number, text, rest) in lines {
+        compiler.out.push_str(&format!("; {}\n{}:\n", text, Symbol::Line(number).label()));
+        if let Err(e) = compiler.statement(rest) {
+            errors.push((e, text.to_string(), line_num));
+        }
+    }
+
+    errors.sort_by_key(|&(_, _, line_num)| line_num);
+
+    compiler.out.push_str("\n.data\n");
+    compiler.out.push_str(&compiler.table.data());
+    Compiled { asml: compiler.out, table: compiler.table, errors }
+}
+
+impl Compiler {
+    fn statement(&mut self, text: &str) -> Result<(), CompileError> {
+        let keyword_end = text.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(text.len());
+        let (keyword, rest) = text.split_at(keyword_end);
+        if keyword.eq_ignore_ascii_case("rem") {
+            return Ok(());
+        }
+        let tokens = tokenize(rest)?;
+
+        match keyword.to_ascii_lowercase().as_str() {
+            "input" => {
+                for part in tokens.split(|token| *token == Token::Comma) {
+                    match part {
+                        [Token::Ident(name)] => {
+                            let label = self.table.add(Symbol::Variable(variable_name(name)?));
+                            self.emit(&format!("read [{}]", label));
+                        }
+                        _ => return Err(CompileError::SyntaxError),
+                    }
+                }
+            }
+            "print" => {
+                for part in tokens.split(|token| *token == Token::Comma) {
+                    let postfix = to_postfix(part)?;
+                    let slot = match postfix.as_slice() {
+                        [Item::Operand(operand)] => self.table.operand(operand),
+                        _ => {
+                            self.expression(&postfix);
+                            let temp = self.table.temp(0);
+                            self.emit(&format!("mov [{}] acc", temp));
+                            temp
+                        }
+                    };
+                    self.emit(&format!("write [{}]", slot));
+                }
+            }
+            "let" => match tokens.as_slice() {
+                [Token::Ident(name), Token::Assign, expr @ ..] => {
+                    let name = variable_name(name)?;
+                    let postfix = to_postfix(expr)?;
+                    self.expression(&postfix);
+                    let label = self.table.add(Symbol::Variable(name));
+                    self.emit(&format!("mov [{}] acc", label));
+                }
+                _ => return Err(CompileError::SyntaxError),
+            },
+            "goto" => {
+                let label = self.line_label(&tokens)?;
+                self.emit(&format!("jmp {}", label));
+            }
+            "if" => {
+                let goto = tokens.iter().position(|token| *token == Token::Ident("goto".to_string())).ok_or(CompileError::SyntaxError)?;
+                let (condition, target) = tokens.split_at(goto);
+                let label = self.line_label(&target[1..])?;
+                let compare = condition.iter().position(|token| matches!(token, Token::Compare(_))).ok_or(CompileError::SyntaxError)?;
+                let Token::Compare(op) = condition[compare] else { unreachable!() };
+
+                // left - right in acc, then the branches test it against 0
+                let mut postfix = to_postfix(&condition[..compare])?;
+                postfix.extend(to_postfix(&condition[compare + 1..])?);
+                postfix.push(Item::Op(Op::Sub));
+                self.expression(&postfix);
+                self.branch(op, &label);
+            }
+            "end" if tokens.is_empty() => self.emit("hlt"),
+            "end" => return Err(CompileError::SyntaxError),
+            _ => return Err(CompileError::UnknownStatement(keyword.to_string())),
+        }
+        Ok(())
+    }
+
+    // goto 30
+    fn line_label(&self, tokens: &[Token]) -> Result<String, CompileError> {
+        match tokens {
+            [Token::Number(number)] if self.table.has_line(*number) => Ok(Symbol::Line(*number).label()),
+            [Token::Number(number)] => Err(CompileError::UndefinedLine(*number)),
+            _ => Err(CompileError::SyntaxError),
+        }
+    }
+
+    // to label when acc op 0, 1: steps over the jmp when the branches can only test the opposite
+    fn branch(&mut self, op: &str, label: &str) {
+        match op {
+            "<" => self.emit(&format!("jlz {}", label)),
+            "==" => self.emit(&format!("jez {}", label)),
+            "<=" => {
+                self.emit(&format!("jlz {}", label));
+                self.emit(&format!("jez {}", label));
+            }
+            ">=" => {
+                self.emit("jlz 1f");
+                self.emit(&format!("jmp {}", label));
+                self.out.push_str("1:\n");
+            }
+            "!=" => {
+                self.emit("jez 1f");
+                self.emit(&format!("jmp {}", label));
+                self.out.push_str("1:\n");
+            }
+            _ => {
+                self.emit("jlz 1f");
+                self.emit("jez 1f");
+                self.emit(&format!("jmp {}", label));
+                self.out.push_str("1:\n");
+            }
+        }
+    }
+
+    // leaves the value of the postfix expression in acc
+    //
+    // the result of an operator stays in acc until another operator needs acc, then it is
+    // stored in the temporary for its place on the stack, tmp0 for the bottom
+    fn expression(&mut self, postfix: &[Item]) {
+        let mut stack: Vec<Value> = Vec::new();
+
+        for item in postfix {
+            match item {
+                Item::Operand(operand) => {
+                    let label = self.table.operand(operand);
+                    stack.push(Value::Slot(label));
+                }
+                Item::Op(Op::Neg) => {
+                    let value = stack.pop().expect("postfix checked when it was made");
+                    let value = self.slot(value, stack.len());
+                    self.spill(&mut stack);
+                    let zero = self.table.operand(&Operand::Constant(0));
+                    self.emit(&format!("mov acc [{}]", zero));
+                    self.emit(&format!("sub acc [{}]", value));
+                    stack.push(Value::Acc);
+                }
+                Item::Op(op) => {
+                    let right = stack.pop().expect("postfix checked when it was made");
+                    let left = stack.pop().expect("postfix checked when it was made");
+                    let mnemonic = match op {
+                        Op::Add => "add",
+                        Op::Sub => "sub",
+                        Op::Mul => "mul",
+                        _ => "div",
+                    };
+                    match (left, right) {
+                        (Value::Acc, Value::Slot(right)) => self.emit(&format!("{} acc [{}]", mnemonic, right)),
+                        // x + (y * z) adds x to the product already in acc
+                        (Value::Slot(left), Value::Acc) if matches!(op, Op::Add | Op::Mul) => {
+                            self.emit(&format!("{} acc [{}]", mnemonic, left));
+                        }
+                        (left, right) => {
+                            let right = self.slot(right, stack.len() + 1);
+                            let left = self.slot(left, stack.len());
+                            self.spill(&mut stack);
+                            self.emit(&format!("mov acc [{}]", left));
+                            self.emit(&format!("{} acc [{}]", mnemonic, right));
+                        }
+                    }
+                    stack.push(Value::Acc);
+                }
+            }
+        }
+
+        if let Some(Value::Slot(label)) = stack.pop() {
+            self.emit(&format!("mov acc [{}]", label));
+        }
+    }
+
+    // a value that is in acc is stored in the temporary for its level
+    fn slot(&mut self, value: Value, level: usize) -> String {
+        match value {
+            Value::Slot(label) => label,
+            Value::Acc => {
+                let temp = self.table.temp(level);
+                self.emit(&format!("mov [{}] acc", temp));
+                temp
+            }
+        }
+    }
+
+    // acc is about to be loaded, a value lower on the stack that is still in it is stored
+    fn spill(&mut self, stack: &mut [Value]) {
+        for (level, value) in stack.iter_mut().enumerate() {
+            if let Value::Acc = value {
+                *value = Value::Slot(self.slot(Value::Acc, level));
+            }
+        }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.out.push_str("    ");
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the instructions of line 10 with the 1: labels the branches use, up to the next line or .data
+    fn line10(source: &str) -> Vec<String> {
+        let compiled = compile(source, "test");
+        assert_eq!(compiled.errors, vec![], "{}", source);
+        compiled.asml.lines()
+            .skip_while(|line| *line != "line10:")
+            .skip(1)
+            .take_while(|line| !line.is_empty() && !line.starts_with(';'))
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    fn errors(source: &str) -> Vec<(CompileError, usize)> {
+        compile(source, "test").errors.into_iter().map(|(e, _, line_num)| (e, line_num)).collect()
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(errors("20 end\n10 end\n"), vec![(CompileError::LineOutOfOrder(10), 1)]);
+        assert_eq!(errors("10 goto 50\n20 end\n"), vec![(CompileError::UndefinedLine(50), 0)]);
+        assert_eq!(errors("10 if x > 0 goto 5\n"), vec![(CompileError::UndefinedLine(5), 0)]);
+        assert_eq!(errors("10 jump 20\n20 end\n"), vec![(CompileError::UnknownStatement("jump".to_string()), 0)]);
+        assert_eq!(errors("10 let 5 = x\n20 if x goto 10\n30 end 5\n40 input x y\n"), vec![
+            (CompileError::SyntaxError, 0),
+            (CompileError::SyntaxError, 1),
+            (CompileError::SyntaxError, 2),
+            (CompileError::SyntaxError, 3),
+        ]);
+        // the lines after a mistake are still compiled
+        assert_eq!(errors("10 frob\n20 goto 99\n30 end\n").len(), 2);
+    }
+
+    #[test]
+    fn nested_expressions_use_temporaries() {
+        assert_eq!(line10("10 let c = (a*a-b*b)/(a-b)\n"), vec![
+            "mov acc [var_a]", "mul acc [var_a]", "mov [tmp0] acc",
+            "mov acc [var_b]", "mul acc [var_b]", "mov [tmp1] acc",
+            "mov acc [tmp0]", "sub acc [tmp1]", "mov [tmp0] acc",
+            "mov acc [var_a]", "sub acc [var_b]", "mov [tmp1] acc",
+            "mov acc [tmp0]", "div acc [tmp1]",
+            "mov [var_c] acc",
+        ]);
+        assert_eq!(line10("10 let y = -(a-10)\n"), vec![
+            "mov acc [var_a]", "sub acc [const_10]", "mov [tmp0] acc",
+            "mov acc [const_0]", "sub acc [tmp0]",
+            "mov [var_y] acc",
+        ]);
+        // a product on the right is added to without a temporary
+        assert_eq!(line10("10 let z = a + b * c\n"), vec!["mov acc [var_b]", "mul acc [var_c]", "add acc [var_a]", "mov [var_z] acc"]);
+        let data = compile("10 let c = (a*a-b*b)/(a-b)\n", "test").asml;
+        assert!(data.ends_with("tmp0: dw 0\ntmp1: dw 0\n"));
+    }
+
+    #[test]
+    fn comparisons() {
+        let cases: [(&str, &[&str]); 6] = [
+            ("<", &["jlz line10"]),
+            ("==", &["jez line10"]),
+            ("<=", &["jlz line10", "jez line10"]),
+            (">=", &["jlz 1f", "jmp line10", "1:"]),
+            ("!=", &["jez 1f", "jmp line10", "1:"]),
+            (">", &["jlz 1f", "jez 1f", "jmp line10", "1:"]),
+        ];
+        for (op, branch) in cases {
+            let mut expected = vec!["mov acc [var_a]", "sub acc [var_b]"];
+            expected.extend(branch);
+            assert_eq!(line10(&format!("10 if a {} b goto 10\n", op)), expected, "{}", op);
+        }
+    }
+}
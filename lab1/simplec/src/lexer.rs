@@ -0,0 +1,81 @@
+use crate::compiler::CompileError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    // variable names and keywords, lower case
+    Ident(String),
+    Number(i32),
+    // + - * /
+    Op(char),
+    LParen,
+    RParen,
+    // == != < <= > >=
+    Compare(&'static str),
+    // let x = ...
+    Assign,
+    Comma,
+}
+
+// splits the part of a line after its line number into tokens
+pub fn tokenize(text: &str) -> Result<Vec<Token>, CompileError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let next = chars.get(pos + 1).copied();
+        match c {
+            c if c.is_whitespace() => pos += 1,
+            c if c.is_ascii_alphabetic() => {
+                let start = pos;
+                while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '_') {
+                    pos += 1;
+                }
+                tokens.push(Token::Ident(chars[start..pos].iter().collect::<String>().to_ascii_lowercase()));
+            }
+            c if c.is_ascii_digit() => {
+                let start = pos;
+                while pos < chars.len() && chars[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+                let text: String = chars[start..pos].iter().collect();
+                let value = text.parse::<i32>().map_err(|_| CompileError::InvalidNumber(text.clone()))?;
+                tokens.push(Token::Number(value));
+            }
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(c));
+                pos += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                pos += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                pos += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                pos += 1;
+            }
+            '=' | '!' | '<' | '>' => {
+                let (token, len) = match (c, next) {
+                    ('=', Some('=')) => (Token::Compare("=="), 2),
+                    ('!', Some('=')) => (Token::Compare("!="), 2),
+                    ('<', Some('=')) => (Token::Compare("<="), 2),
+                    ('>', Some('=')) => (Token::Compare(">="), 2),
+                    ('<', _) => (Token::Compare("<"), 1),
+                    ('>', _) => (Token::Compare(">"), 1),
+                    ('=', _) => (Token::Assign, 1),
+                    _ => return Err(CompileError::UnexpectedCharacter(c)),
+                };
+                tokens.push(token);
+                pos += len;
+            }
+            _ => return Err(CompileError::UnexpectedCharacter(c)),
+        }
+    }
+
+    Ok(tokens)
+}
@@ -0,0 +1,44 @@
+use clap::Parser;
+
+mod compiler;
+mod lexer;
+mod postfix;
+mod symbols;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(short, long)]
+    input_file: String,
+    /// asml for the assembler
+    #[arg(short, long, default_value = "output.asml")]
+    output_file: String,
+    /// print every line number, variable and constant with the label it was given
+    #[arg(short = 't', long)]
+    symbol_table: bool,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let input = std::fs::read_to_string(&args.input_file)
+        .expect("Failed to read input file");
+
+    let compiled = compiler::compile(&input, &args.input_file);
+
+    if !compiled.errors.is_empty() {
+        for (error, line, line_num) in &compiled.errors {
+            println!("Error on line {}: {:?} - {}", line_num + 1, error, line);
+        }
+        std::process::exit(1);
+    }
+
+    if args.symbol_table {
+        print!("{}", compiled.table.report());
+    }
+
+    std::fs::write(&args.output_file, compiled.asml)
+        .expect("Failed to write output file");
+
+    println!("Compilation successful! Output written to {}", args.output_file);
+}
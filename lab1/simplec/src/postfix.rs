@@ -0,0 +1,113 @@
+use crate::compiler::CompileError;
+use crate::lexer::Token;
+
+// infix expressions to postfix, x + 2 * (y - 1) is x 2 y 1 - * +
+//
+// * and / bind tighter than + and -, all of them go left to right
+// a - where an operand is expected negates it, -x * y is (0 - x) * y
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Variable(String),
+    Constant(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+}
+
+impl Op {
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div => 2,
+            Op::Neg => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Operand(Operand),
+    Op(Op),
+}
+
+// what is on the operator stack while converting
+enum Pending {
+    Op(Op),
+    LParen,
+}
+
+pub fn to_postfix(tokens: &[Token]) -> Result<Vec<Item>, CompileError> {
+    let mut out = Vec::new();
+    let mut pending: Vec<Pending> = Vec::new();
+    // an operand or ( comes next, otherwise an operator or )
+    let mut want_operand = true;
+
+    for token in tokens {
+        match token {
+            Token::Number(value) if want_operand => {
+                out.push(Item::Operand(Operand::Constant(*value)));
+                want_operand = false;
+            }
+            Token::Ident(name) if want_operand => {
+                out.push(Item::Operand(Operand::Variable(variable_name(name)?)));
+                want_operand = false;
+            }
+            Token::LParen if want_operand => pending.push(Pending::LParen),
+            Token::Op('-') if want_operand => pending.push(Pending::Op(Op::Neg)),
+            Token::Op(c) if !want_operand => {
+                let op = match c {
+                    '+' => Op::Add,
+                    '-' => Op::Sub,
+                    '*' => Op::Mul,
+                    _ => Op::Div,
+                };
+                while let Some(Pending::Op(top)) = pending.last() {
+                    if top.precedence() < op.precedence() {
+                        break;
+                    }
+                    out.push(Item::Op(*top));
+                    pending.pop();
+                }
+                pending.push(Pending::Op(op));
+                want_operand = true;
+            }
+            Token::RParen if !want_operand => loop {
+                match pending.pop() {
+                    Some(Pending::Op(op)) => out.push(Item::Op(op)),
+                    Some(Pending::LParen) => break,
+                    None => return Err(CompileError::UnbalancedParentheses),
+                }
+            },
+            _ => return Err(CompileError::SyntaxError),
+        }
+    }
+    if want_operand {
+        return Err(CompileError::SyntaxError);
+    }
+
+    while let Some(top) = pending.pop() {
+        match top {
+            Pending::Op(op) => out.push(Item::Op(op)),
+            Pending::LParen => return Err(CompileError::UnbalancedParentheses),
+        }
+    }
+
+    Ok(out)
+}
+
+const KEYWORDS: [&str; 7] = ["rem", "input", "let", "print", "goto", "if", "end"];
+
+// a letter then letters, digits and _, not a keyword
+pub fn variable_name(name: &str) -> Result<String, CompileError> {
+    if KEYWORDS.contains(&name) {
+        return Err(CompileError::InvalidVariable(name.to_string()));
+    }
+    Ok(name.to_string())
+}
@@ -0,0 +1,88 @@
+use crate::postfix::Operand;
+
+// every line number, variable and constant the program uses, with the asml label it gets
+//
+// lines are line10, variables var_x and constants const_5 or const_m5 for -5, variables and
+// constants are given a data word the first time they are used, in that order
+// temporaries for expressions are tmp0, tmp1 ... one for each level the evaluation goes down
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Symbol {
+    Line(i32),
+    Variable(String),
+    Constant(i32),
+}
+
+impl Symbol {
+    pub fn label(&self) -> String {
+        match self {
+            Symbol::Line(number) => format!("line{}", number),
+            Symbol::Variable(name) => format!("var_{}", name),
+            Symbol::Constant(value) if *value < 0 => format!("const_m{}", -value),
+            Symbol::Constant(value) => format!("const_{}", value),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    temps: usize,
+}
+
+impl SymbolTable {
+    pub fn add(&mut self, symbol: Symbol) -> String {
+        let label = symbol.label();
+        if !self.symbols.contains(&symbol) {
+            self.symbols.push(symbol);
+        }
+        label
+    }
+
+    pub fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Variable(name) => self.add(Symbol::Variable(name.clone())),
+            Operand::Constant(value) => self.add(Symbol::Constant(*value)),
+        }
+    }
+
+    pub fn temp(&mut self, level: usize) -> String {
+        self.temps = self.temps.max(level + 1);
+        format!("tmp{}", level)
+    }
+
+    pub fn has_line(&self, number: i32) -> bool {
+        self.symbols.contains(&Symbol::Line(number))
+    }
+
+    // the .data section, one word per variable, constant and temporary
+    pub fn data(&self) -> String {
+        let mut out = String::new();
+        for symbol in &self.symbols {
+            let value = match symbol {
+                Symbol::Line(_) => continue,
+                Symbol::Variable(_) => 0,
+                Symbol::Constant(value) => *value,
+            };
+            out.push_str(&format!("{}: dw {}\n", symbol.label(), value));
+        }
+        for level in 0..self.temps {
+            out.push_str(&format!("tmp{}: dw 0\n", level));
+        }
+        out
+    }
+
+    // symbol kind label, for --symbol-table
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for symbol in &self.symbols {
+            let (name, kind) = match symbol {
+                Symbol::Line(number) => (number.to_string(), "line"),
+                Symbol::Variable(name) => (name.clone(), "variable"),
+                Symbol::Constant(value) => (value.to_string(), "constant"),
+            };
+            out.push_str(&format!("{:<10} {:<9} {}\n", name, kind, symbol.label()));
+        }
+        out
+    }
+}